@@ -0,0 +1,323 @@
+use std::task::{Context, Poll};
+
+use axum::http::{header, HeaderMap, Method, Request};
+use color_eyre::{
+    eyre::{eyre, OptionExt},
+    Result,
+};
+use futures::future::Either;
+use rand::distr::{Alphanumeric, SampleString as _};
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+use surrealdb::{Datetime, RecordId};
+use tower::{util::Oneshot, Layer, Service, ServiceExt as _};
+
+use crate::{
+    schema::{Created, PartialCreated, PartialToken, Token},
+    state::SurrealDb,
+};
+
+const TOKEN_PREFIX: &str = "so_";
+
+/// Tokens are long random strings, so a plain SHA-256 is enough to keep them safe at rest
+/// while still letting us look them up directly by hash
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Resources tokens can be given access to, each with a `read` and a `write` scope like `link:read`
+pub const RESOURCES: &[&str] = &["link", "shortcut", "file", "paste", "token", "admin"];
+
+/// Scope giving access to everything
+pub const ALL_SCOPES: &str = "*";
+
+/// Returns whether `scope` is `*` or the `read` or `write` scope of a known resource
+pub fn is_valid_scope(scope: &str) -> bool {
+    scope == ALL_SCOPES
+        || scope.split_once(':').is_some_and(|(resource, action)| {
+            RESOURCES.contains(&resource) && matches!(action, "read" | "write")
+        })
+}
+
+/// What an API token needs to be allowed to use a route, declared with [`RouteType::scoped`]
+///
+/// [`RouteType::scoped`]: crate::routes::RouteType::scoped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteScope {
+    /// Any token can be used
+    Any,
+
+    /// The `read` scope of the resource for GET, HEAD and OPTIONS requests, and its `write` scope otherwise
+    Resource(&'static str),
+}
+
+/// Returns the scope a token needs for a request to a route, or `None` if any token will do.
+///
+/// Routes that don't declare a scope can only be used with tokens that have access to everything.
+pub fn required_scope(route: Option<RouteScope>, method: &Method) -> Option<String> {
+    let resource = match route {
+        None => return Some(ALL_SCOPES.to_string()),
+        Some(RouteScope::Any) => return None,
+        Some(RouteScope::Resource(resource)) => resource,
+    };
+
+    let action = if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        "read"
+    } else {
+        "write"
+    };
+
+    Some(format!("{resource}:{action}"))
+}
+
+/// Returns the API token the request is authenticated with, if any
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Applies the wrapped layer only to requests that aren't authenticated with an API token, so that
+/// scripts get an error instead of being redirected to the login page
+#[derive(Clone)]
+pub struct UnlessBearerLayer<L>(pub L);
+
+impl<S: Clone, L: Layer<S>> Layer<S> for UnlessBearerLayer<L> {
+    type Service = UnlessBearer<S, L::Service>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        UnlessBearer {
+            bearer: inner.clone(),
+            other: self.0.layer(inner),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UnlessBearer<S, T> {
+    bearer: S,
+    other: T,
+}
+
+impl<S, T, B> Service<Request<B>> for UnlessBearer<S, T>
+where
+    S: Service<Request<B>> + Clone,
+    T: Service<Request<B>, Response = S::Response, Error = S::Error> + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<Oneshot<S, Request<B>>, Oneshot<T, Request<B>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // the service that handles the request is only known once it's called, and `oneshot` waits
+        // for it to be ready
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if bearer_token(request.headers()).is_some() {
+            Either::Left(self.bearer.clone().oneshot(request))
+        } else {
+            Either::Right(self.other.clone().oneshot(request))
+        }
+    }
+}
+
+/// The user a token belongs to and what it can be used for
+#[derive(Debug, Deserialize)]
+pub struct TokenOwner {
+    pub user: RecordId,
+    pub scopes: Vec<String>,
+}
+
+impl TokenOwner {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|granted| granted == ALL_SCOPES || granted == scope)
+    }
+}
+
+/// Creates a new token for `user` and returns it along with the token in plain text
+pub async fn create_token(
+    db: &SurrealDb,
+    user: &RecordId,
+    name: &str,
+    scopes: Vec<String>,
+    expires_at: Option<Datetime>,
+) -> Result<(Token, String)> {
+    let token = format!(
+        "{TOKEN_PREFIX}{}",
+        Alphanumeric.sample_string(&mut rand::rng(), 40)
+    );
+
+    let created_token: Token = db
+        .create("token")
+        .content(PartialToken {
+            name: name.to_string(),
+            hash: hash_token(&token),
+            scopes,
+            expires_at,
+        })
+        .await?
+        .ok_or_eyre("Failed to create token")?;
+
+    let token_created_rel: Vec<Created> = db
+        .insert("created")
+        .relation(PartialCreated {
+            object: created_token.id.clone(),
+            user: user.clone(),
+        })
+        .await?;
+
+    if token_created_rel.is_empty() {
+        let _: Option<Token> = db.delete(&created_token.id).await?;
+        return Err(eyre!("Failed to create token"));
+    }
+
+    Ok((created_token, token))
+}
+
+/// Deletes a token, which makes it stop working right away
+pub async fn revoke_token(db: &SurrealDb, token: &RecordId) -> Result<()> {
+    db.query(
+        "
+            DELETE $token<-created;
+            DELETE $token;
+        ",
+    )
+    .bind(("token", token.clone()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Creates a new token for `user` and returns it in plain text.
+///
+/// Any tokens of the user with the same name are revoked, so regenerating a token invalidates the old one.
+pub async fn replace_token(
+    db: &SurrealDb,
+    user: &RecordId,
+    name: &str,
+    scopes: &[&str],
+) -> Result<String> {
+    db.query(
+        "
+            LET $tokens = SELECT VALUE id FROM token WHERE name = $name AND array::any(array::matches(<-created<-user.id, $user));
+            DELETE array::flatten(SELECT VALUE <-created FROM $tokens);
+            DELETE $tokens;
+        ",
+    )
+    .bind(("user", user.clone()))
+    .bind(("name", name.to_string()))
+    .await?
+    .check()?;
+
+    let scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+    let (_, token) = create_token(db, user, name, scopes, None).await?;
+
+    Ok(token)
+}
+
+/// Returns the owner of the given token, unless it doesn't exist or has expired.
+///
+/// Tokens created before scopes were introduced have access to everything.
+pub async fn owner_of_token(db: &SurrealDb, token: &str) -> Result<Option<TokenOwner>> {
+    Ok(db
+        .query("SELECT array::first(<-created<-user.id) AS user, scopes ?? [$all] AS scopes FROM ONLY token WHERE hash = $hash AND (expires_at = NONE OR expires_at > time::now())")
+        .bind(("hash", hash_token(token)))
+        .bind(("all", ALL_SCOPES))
+        .await?
+        .take(0)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn owner(scopes: &[&str]) -> TokenOwner {
+        TokenOwner {
+            user: RecordId::from_table_key("user", "someone"),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn validates_scopes() {
+        assert!(is_valid_scope("*"));
+        assert!(is_valid_scope("link:read"));
+        assert!(is_valid_scope("paste:write"));
+        assert!(is_valid_scope("admin:write"));
+        assert!(!is_valid_scope("link"));
+        assert!(!is_valid_scope("link:delete"));
+        assert!(!is_valid_scope("upload:read"));
+        assert!(!is_valid_scope("link:read:write"));
+    }
+
+    #[test]
+    fn routes_need_the_scope_matching_the_method() {
+        let link = Some(RouteScope::Resource("link"));
+
+        assert_eq!(
+            required_scope(link, &Method::GET).as_deref(),
+            Some("link:read")
+        );
+        assert_eq!(
+            required_scope(link, &Method::HEAD).as_deref(),
+            Some("link:read")
+        );
+        assert_eq!(
+            required_scope(link, &Method::POST).as_deref(),
+            Some("link:write")
+        );
+        assert_eq!(
+            required_scope(link, &Method::DELETE).as_deref(),
+            Some("link:write")
+        );
+    }
+
+    #[test]
+    fn routes_without_a_scope_need_access_to_everything() {
+        assert_eq!(
+            required_scope(None, &Method::GET).as_deref(),
+            Some(ALL_SCOPES)
+        );
+        assert_eq!(required_scope(Some(RouteScope::Any), &Method::POST), None);
+
+        assert!(owner(&["*"]).has_scope(ALL_SCOPES));
+        assert!(!owner(&["link:read", "link:write"]).has_scope(ALL_SCOPES));
+    }
+
+    #[test]
+    fn scopes_are_granted_exactly() {
+        let owner = owner(&["link:read", "file:write"]);
+
+        assert!(owner.has_scope("link:read"));
+        assert!(owner.has_scope("file:write"));
+        assert!(!owner.has_scope("link:write"));
+        assert!(!owner.has_scope("file:read"));
+    }
+
+    #[test]
+    fn reads_bearer_tokens() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer so_abc "),
+        );
+        assert_eq!(bearer_token(&headers), Some("so_abc"));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcjpwYXNz"),
+        );
+        assert_eq!(bearer_token(&headers), None);
+    }
+}
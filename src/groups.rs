@@ -0,0 +1,75 @@
+//! OIDC groups as SurrealDB records, so that links and shortcuts can be owned by a whole team.
+//!
+//! Groups are `oidc_group` records keyed on their name, created the first time a member logs in.
+//! Memberships are `member_of` edges that are replaced on every login, and group-owned objects have
+//! an `owns` edge from the group. The `fn::can_access` database function checks both personal and
+//! group ownership.
+
+use color_eyre::Result;
+use surrealdb::RecordId;
+
+use crate::state::SurrealDb;
+
+pub fn group_id(name: &str) -> RecordId {
+    RecordId::from_table_key("oidc_group", name)
+}
+
+/// Makes `user` a member of exactly `groups`, creating the groups that don't exist yet
+pub async fn sync_groups(db: &SurrealDb, user: &RecordId, groups: &[String]) -> Result<()> {
+    db.query(
+        "
+            BEGIN;
+            DELETE $user->member_of;
+            FOR $name IN $groups {
+                LET $group = type::thing('oidc_group', $name);
+                UPSERT $group SET name = $name;
+                RELATE $user->member_of->$group;
+            };
+            COMMIT;
+        ",
+    )
+    .bind(("user", user.clone()))
+    .bind(("groups", groups.to_vec()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Returns the group called `name` if `user` is a member of it
+pub async fn membership(db: &SurrealDb, user: &RecordId, name: &str) -> Result<Option<RecordId>> {
+    Ok(db
+        .query("SELECT VALUE id FROM ONLY $group WHERE <-member_of<-user CONTAINS $user")
+        .bind(("group", group_id(name)))
+        .bind(("user", user.clone()))
+        .await?
+        .take(0)?)
+}
+
+/// Makes `group` the owner of `objects`, or makes them personal again if it's `None`.
+///
+/// The user who created an object keeps access to it either way.
+pub async fn set_owner(
+    db: &SurrealDb,
+    objects: &[RecordId],
+    group: Option<RecordId>,
+) -> Result<()> {
+    db.query(
+        "
+            BEGIN;
+            DELETE owns WHERE out INSIDE $objects;
+            IF $group != NONE {
+                FOR $object IN $objects {
+                    RELATE $group->owns->$object;
+                };
+            };
+            COMMIT;
+        ",
+    )
+    .bind(("objects", objects.to_vec()))
+    .bind(("group", group))
+    .await?
+    .check()?;
+
+    Ok(())
+}
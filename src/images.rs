@@ -0,0 +1,229 @@
+use std::io::Cursor;
+
+use axum::body::Bytes;
+use color_eyre::Result;
+use futures::TryStreamExt as _;
+use image::{metadata::Orientation, DynamicImage, ImageDecoder as _, ImageFormat, ImageReader};
+use img_parts::{DynImage, ImageEXIF as _};
+use tracing::warn;
+
+use crate::{schema::PartialFile, settings::Images, storage::Storage};
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+const EXIF_SHORT: u16 = 3;
+
+/// Returns the storage key of a thumbnail of the file with the given blob key
+pub fn thumbnail_key(key: &str, size: u32) -> String {
+    format!("thumbnails/{key}/{size}")
+}
+
+/// Returns the format the thumbnails of a file with the given content type are stored in.
+///
+/// Formats that can be transparent get PNG thumbnails, everything else gets JPEG.
+pub fn thumbnail_format(content_type: &str) -> ImageFormat {
+    match content_type {
+        "image/png" | "image/gif" | "image/webp" => ImageFormat::Png,
+        _ => ImageFormat::Jpeg,
+    }
+}
+
+/// Removes the thumbnails of the file with the given blob key
+pub async fn remove_thumbnails(storage: &Storage, key: &str, sizes: &[u32]) {
+    for size in sizes {
+        let key = thumbnail_key(key, *size);
+        if let Err(e) = storage.delete(&key).await {
+            warn!(error = ?e, key, "Failed to remove thumbnail");
+        }
+    }
+}
+
+/// Told to uploaders whose images are refused because their metadata couldn't be stripped
+pub const UNSTRIPPABLE_MESSAGE: &str = "The metadata of the image couldn't be stripped. Upload it with keep_metadata set to store it as it is";
+
+/// Processes an uploaded image stored under `key`: strips its metadata (unless `keep_metadata` is
+/// set), records its dimensions and generates thumbnails.
+///
+/// Files that aren't images are left alone. As the metadata can contain the location the image was
+/// taken at, images it can't be stripped from are refused with a message for the uploader, and
+/// the caller has to remove them. Other processing errors are only logged, as the image can still
+/// be stored without thumbnails.
+pub async fn process_upload(
+    storage: &Storage,
+    settings: &Images,
+    key: &str,
+    file: &mut PartialFile,
+    keep_metadata: bool,
+) -> Result<(), &'static str> {
+    let readable = ImageFormat::from_mime_type(&file.content_type)
+        .is_some_and(|format| format.reading_enabled());
+
+    if !readable {
+        return Ok(());
+    }
+
+    // big images aren't loaded into memory
+    if file.size > settings.max_size {
+        return if keep_metadata {
+            Ok(())
+        } else {
+            Err(UNSTRIPPABLE_MESSAGE)
+        };
+    }
+
+    match process_image(storage, settings, key, file, keep_metadata).await {
+        Ok(()) => Ok(()),
+        Err(e) if keep_metadata => {
+            warn!(error = ?e, key, "Failed to process uploaded image");
+            Ok(())
+        }
+        Err(e) => {
+            warn!(error = ?e, key, "Failed to strip the metadata of uploaded image");
+            Err(UNSTRIPPABLE_MESSAGE)
+        }
+    }
+}
+
+/// Strips the metadata of an image and generates its thumbnails.
+///
+/// Only fails if the metadata couldn't be stripped, as thumbnail errors are logged.
+async fn process_image(
+    storage: &Storage,
+    settings: &Images,
+    key: &str,
+    file: &mut PartialFile,
+    keep_metadata: bool,
+) -> Result<()> {
+    let mut data = Vec::with_capacity(file.size.try_into()?);
+    let mut blob = storage.get(key).await?;
+    while let Some(chunk) = blob.try_next().await? {
+        data.extend_from_slice(&chunk);
+    }
+
+    let format = thumbnail_format(&file.content_type);
+    let sizes = settings.thumbnail_sizes.clone();
+
+    let (stripped, decoded) = tokio::task::spawn_blocking(move || {
+        let data = Bytes::from(data);
+
+        let decoded = decode_image(&data);
+
+        // the metadata doesn't depend on the image being decodable, but the orientation is kept
+        // when it's known
+        let stripped = if keep_metadata {
+            None
+        } else {
+            let orientation = decoded
+                .as_ref()
+                .map_or(Orientation::NoTransforms, |(_, orientation)| *orientation);
+
+            strip_metadata(data, orientation)?
+        };
+
+        let decoded = decoded.and_then(|(image, _)| {
+            let thumbnails = sizes
+                .iter()
+                .map(|&size| Ok((size, encode_thumbnail(&image, size, format)?)))
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok((image.width(), image.height(), thumbnails))
+        });
+
+        Result::<_>::Ok((stripped, decoded))
+    })
+    .await??;
+
+    if let Some(stripped) = stripped {
+        file.size = storage.put_bytes(key, stripped).await?;
+    }
+
+    let (width, height, thumbnails) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            warn!(error = ?e, key, "Failed to generate thumbnails of uploaded image");
+            return Ok(());
+        }
+    };
+
+    file.width = Some(width);
+    file.height = Some(height);
+
+    for (size, thumbnail) in thumbnails {
+        let thumbnail_key = thumbnail_key(key, size);
+        match storage.put_bytes(&thumbnail_key, thumbnail).await {
+            Ok(_) => file.thumbnails.push(size),
+            Err(e) => warn!(error = ?e, key = thumbnail_key, "Failed to store thumbnail"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes an image, turned the right way up, along with the orientation it was stored in
+fn decode_image(data: &Bytes) -> Result<(DynamicImage, Orientation)> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    Ok((image, orientation))
+}
+
+fn encode_thumbnail(image: &DynamicImage, size: u32, format: ImageFormat) -> Result<Bytes> {
+    let thumbnail = image.thumbnail(size, size);
+
+    // JPEG doesn't support transparency
+    let thumbnail = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail.into_rgb8()),
+        _ => thumbnail,
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut buffer, format)?;
+
+    Ok(buffer.into_inner().into())
+}
+
+/// Removes the EXIF metadata (including GPS coordinates) from an image, keeping only its
+/// orientation so that it's still displayed the right way up.
+///
+/// Returns `None` if there was nothing to strip.
+fn strip_metadata(data: Bytes, orientation: Orientation) -> Result<Option<Bytes>> {
+    let Some(mut image) = DynImage::from_bytes(data)? else {
+        return Ok(None);
+    };
+
+    if image.exif().is_none() {
+        return Ok(None);
+    }
+
+    image.set_exif(
+        (orientation != Orientation::NoTransforms).then(|| orientation_exif(orientation)),
+    );
+
+    Ok(Some(image.encoder().bytes()))
+}
+
+/// Builds a minimal EXIF (TIFF) block containing nothing but the orientation tag
+fn orientation_exif(orientation: Orientation) -> Bytes {
+    let mut exif = Vec::with_capacity(26);
+
+    // big-endian TIFF header, followed by the offset of the first IFD
+    exif.extend_from_slice(b"MM\0\x2a");
+    exif.extend_from_slice(&8u32.to_be_bytes());
+
+    // an IFD with a single entry: tag, type, count and the value padded to 4 bytes
+    exif.extend_from_slice(&1u16.to_be_bytes());
+    exif.extend_from_slice(&EXIF_ORIENTATION_TAG.to_be_bytes());
+    exif.extend_from_slice(&EXIF_SHORT.to_be_bytes());
+    exif.extend_from_slice(&1u32.to_be_bytes());
+    exif.extend_from_slice(&u16::from(orientation.to_exif()).to_be_bytes());
+    exif.extend_from_slice(&[0, 0]);
+
+    // no next IFD
+    exif.extend_from_slice(&0u32.to_be_bytes());
+
+    exif.into()
+}
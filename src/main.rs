@@ -1,9 +1,16 @@
+mod api_token;
 mod axum_error;
+mod groups;
+mod images;
+mod password;
+mod quota;
+mod reaper;
 mod routes;
 mod schema;
 mod serialize_recordid;
 mod settings;
 mod state;
+mod storage;
 mod userid_extractor;
 
 use std::{net::SocketAddr, ops::Deref, sync::Arc, time::Duration};
@@ -42,8 +49,10 @@ use utoipa_scalar::{Scalar, Servable as _};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
+    api_token::UnlessBearerLayer,
     settings::{env_name, Settings},
     state::{AppState, InnerState},
+    storage::init_storage,
 };
 
 #[derive(OpenApi)]
@@ -52,7 +61,9 @@ struct ApiDoc;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct GroupClaims {
-    groups: Vec<String>,
+    /// Not every identity provider sends the groups claim
+    #[serde(default)]
+    pub groups: Vec<String>,
 }
 impl axum_oidc::AdditionalClaims for GroupClaims {}
 impl openidconnect::AdditionalClaims for GroupClaims {}
@@ -73,12 +84,18 @@ async fn main() -> Result<()> {
     let settings = Arc::new(Settings::try_load()?);
 
     let db = init_surrealdb(&settings).await?;
+    let storage = init_storage(&settings)
+        .await
+        .wrap_err("failed to initialize storage")?;
 
     let app_state = AppState::new(InnerState {
         settings: settings.clone(),
         db,
+        storage,
     });
 
+    init_reaper(app_state.clone());
+
     let session_layer = init_session_store(&app_state.db).await;
     let app = init_axum(app_state, session_layer).await?;
     let listener = init_listener(&settings).await?;
@@ -91,9 +108,12 @@ async fn main() -> Result<()> {
         settings.general.public_url
     );
 
-    axum::serve(listener, app.into_make_service())
-        .await
-        .wrap_err("failed to run server")?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .wrap_err("failed to run server")?;
 
     Ok(())
 }
@@ -158,6 +178,18 @@ async fn init_surrealdb(settings: &Settings) -> Result<SurrealDb> {
     Ok(db)
 }
 
+fn init_reaper(state: AppState) {
+    tokio::task::spawn(async move {
+        let mut timer = interval(Duration::from_secs(60));
+        loop {
+            timer.tick().await;
+            if let Err(e) = reaper::delete_expired(&state).await {
+                error!(error = ?e, "Failed to delete expired objects");
+            }
+        }
+    });
+}
+
 async fn init_session_store(db: &SurrealDb) -> SessionManagerLayer<SurrealSessionStore<Any>> {
     let session_store = SurrealSessionStore::new(db.clone(), "session".to_string());
 
@@ -254,7 +286,8 @@ async fn init_axum(
                 },
             );
 
-        autologin_router.layer(oidc_login_service)
+        // scripts using API tokens never have a session, so they aren't sent to the login page
+        autologin_router.layer(UnlessBearerLayer(oidc_login_service))
     };
 
     let router = OpenApiRouter::with_openapi(ApiDoc::openapi()).merge(autologin_router);
@@ -0,0 +1,132 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use color_eyre::{eyre::OptionExt, Result};
+use futures::StreamExt as _;
+use serde::Deserialize;
+use surrealdb::RecordId;
+
+use crate::{
+    settings::{Quota, Quotas},
+    state::SurrealDb,
+    storage::ByteStream,
+};
+
+/// How much storage a user is using, along with the limits that apply to them
+#[derive(Debug, Clone)]
+pub struct Usage {
+    /// Total size of the user's files, in bytes
+    pub files: u64,
+
+    /// Total announced size of the user's unfinished uploads, in bytes
+    pub uploads: u64,
+
+    pub quota: Quota,
+}
+
+impl Usage {
+    pub fn used(&self) -> u64 {
+        self.files + self.uploads
+    }
+
+    /// How many more bytes the user can store, if there's a limit
+    pub fn remaining(&self) -> Option<u64> {
+        self.quota
+            .max_total_size
+            .map(|max| max.saturating_sub(self.used()))
+    }
+
+    /// The size of the largest upload the user can make right now, if there's a limit
+    pub fn max_upload_size(&self) -> Option<u64> {
+        match (self.quota.max_upload_size, self.remaining()) {
+            (Some(max), Some(remaining)) => Some(max.min(remaining)),
+            (max, remaining) => max.or(remaining),
+        }
+    }
+}
+
+/// Works out the quota of a member of `groups`.
+///
+/// Group overrides take precedence over the defaults. If several of the user's groups override
+/// the same limit, the most generous one wins.
+pub fn quota_for_groups(settings: &Quotas, groups: &[String]) -> Quota {
+    let overrides: Vec<&Quota> = groups
+        .iter()
+        .filter_map(|group| settings.groups.get(group))
+        .collect();
+
+    Quota {
+        max_upload_size: overrides
+            .iter()
+            .filter_map(|quota| quota.max_upload_size)
+            .max()
+            .or(settings.default.max_upload_size),
+        max_total_size: overrides
+            .iter()
+            .filter_map(|quota| quota.max_total_size)
+            .max()
+            .or(settings.default.max_total_size),
+    }
+}
+
+#[derive(Deserialize)]
+struct UsageRow {
+    groups: Vec<String>,
+    files: u64,
+    uploads: u64,
+}
+
+/// Works out how much storage `user` is using and which quota applies to them
+pub async fn usage(db: &SurrealDb, settings: &Quotas, user: &RecordId) -> Result<Usage> {
+    let row: UsageRow = db
+        .query(
+            "SELECT VALUE {groups: groups ?? [], files: math::sum(->created->file.size), uploads: math::sum(->created->upload.length)} FROM ONLY $user",
+        )
+        .bind(("user", user.clone()))
+        .await?
+        .take::<Option<UsageRow>>(0)?
+        .ok_or_eyre("User not found")?;
+
+    Ok(Usage {
+        files: row.files,
+        uploads: row.uploads,
+        quota: quota_for_groups(settings, &row.groups),
+    })
+}
+
+pub fn exceeded_message(limit: u64) -> String {
+    format!("Upload exceeds your quota: at most {limit} bytes can be uploaded right now")
+}
+
+/// Wraps `body` so that it fails as soon as more than `limit` bytes have been read, instead of
+/// streaming the whole thing.
+///
+/// The returned flag is set if the limit was exceeded.
+pub fn limit_stream(body: ByteStream<'_>, limit: Option<u64>) -> (ByteStream<'_>, Arc<AtomicBool>) {
+    let exceeded = Arc::new(AtomicBool::new(false));
+
+    let Some(limit) = limit else {
+        return (body, exceeded);
+    };
+
+    let flag = exceeded.clone();
+    let mut received: u64 = 0;
+
+    let body = body
+        .map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+
+            if received > limit {
+                flag.store(true, Ordering::Relaxed);
+                return Err(std::io::Error::other(exceeded_message(limit)));
+            }
+
+            Ok(chunk)
+        })
+        .boxed();
+
+    (body, exceeded)
+}
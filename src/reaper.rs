@@ -0,0 +1,77 @@
+use color_eyre::Result;
+use surrealdb::RecordId;
+use tracing::{error, info};
+
+use crate::{
+    api_token,
+    routes::api::{file, tus},
+    state::AppState,
+};
+
+/// Deletes expired files (along with their shortcuts), abandoned partial uploads and expired API tokens.
+///
+/// Objects that fail to be deleted are logged and skipped, so they don't hold up the rest; they're
+/// tried again on the next pass.
+pub async fn delete_expired(state: &AppState) -> Result<()> {
+    let files: Vec<RecordId> = state
+        .db
+        .query("SELECT VALUE id FROM file WHERE expires_at != NONE AND expires_at < time::now()")
+        .await?
+        .take(0)?;
+
+    let mut deleted_files = 0;
+    for id in &files {
+        match file::delete_file(&state.db, &state.storage, id).await {
+            Ok(()) => deleted_files += 1,
+            Err(e) => {
+                error!(error = ?e, file = %id, "Failed to delete expired file");
+                continue;
+            }
+        }
+    }
+
+    let uploads: Vec<RecordId> = state
+        .db
+        .query("SELECT VALUE id FROM upload WHERE expires_at != NONE AND expires_at < time::now()")
+        .await?
+        .take(0)?;
+
+    let mut deleted_uploads = 0;
+    for id in &uploads {
+        match tus::delete_upload_record(&state.db, &state.settings, id).await {
+            Ok(()) => deleted_uploads += 1,
+            Err(e) => {
+                error!(error = ?e, upload = %id, "Failed to delete expired upload");
+                continue;
+            }
+        }
+    }
+
+    let tokens: Vec<RecordId> = state
+        .db
+        .query("SELECT VALUE id FROM token WHERE expires_at != NONE AND expires_at < time::now()")
+        .await?
+        .take(0)?;
+
+    let mut revoked_tokens = 0;
+    for id in &tokens {
+        match api_token::revoke_token(&state.db, id).await {
+            Ok(()) => revoked_tokens += 1,
+            Err(e) => {
+                error!(error = ?e, token = %id, "Failed to revoke expired token");
+                continue;
+            }
+        }
+    }
+
+    if deleted_files > 0 || deleted_uploads > 0 || revoked_tokens > 0 {
+        info!(
+            files = deleted_files,
+            uploads = deleted_uploads,
+            tokens = revoked_tokens,
+            "Deleted expired objects"
+        );
+    }
+
+    Ok(())
+}
@@ -1,11 +1,11 @@
-mod api;
+pub mod api;
 mod dash;
 mod shortcut_handler;
 
-use axum::routing::MethodRouter;
+use axum::{routing::MethodRouter, Extension};
 use utoipa_axum::router::UtoipaMethodRouter;
 
-use crate::state::AppState;
+use crate::{api_token::RouteScope, state::AppState};
 
 pub fn routes() -> Vec<Route> {
     [shortcut_handler::routes(), api::routes(), dash::routes()].concat()
@@ -18,3 +18,17 @@ pub enum RouteType {
     OpenApi(UtoipaMethodRouter<AppState>),
     Undocumented((&'static str, MethodRouter<AppState>)),
 }
+
+impl RouteType {
+    /// Declares what API tokens need to be allowed to use the route
+    pub fn scoped(self, scope: RouteScope) -> Self {
+        match self {
+            Self::OpenApi((schemas, paths, router)) => {
+                Self::OpenApi((schemas, paths, router.layer(Extension(scope))))
+            }
+            Self::Undocumented((path, router)) => {
+                Self::Undocumented((path, router.layer(Extension(scope))))
+            }
+        }
+    }
+}
@@ -1,16 +1,31 @@
+mod admin;
+pub mod file;
 mod health;
 mod info;
 pub mod link;
-mod shortcut;
+mod me;
+mod pagination;
+mod paste;
+pub mod shortcut;
+mod token;
+pub mod tus;
+mod uploader;
 
 use super::Route;
 
 pub fn routes() -> Vec<Route> {
     [
+        admin::routes(),
+        file::routes(),
         health::routes(),
         info::routes(),
         link::routes(),
+        me::routes(),
+        paste::routes(),
         shortcut::routes(),
+        token::routes(),
+        tus::routes(),
+        uploader::routes(),
     ]
     .concat()
 }
@@ -0,0 +1,599 @@
+//! Admin API, for members of the admin group configured in `oidc.admin_group`.
+//!
+//! Admins can see everyone's links and shortcuts, delete any object, and transfer objects to
+//! another user, for example to clean up after someone who left.
+
+use axum::{
+    extract::{OriginalUri, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use surrealdb::{Datetime, RecordId};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::RouteScope,
+    axum_error::AxumResult,
+    routes::RouteType,
+    serialize_recordid::{serialize_recordid_as_key, serialize_recordid_vec_as_key},
+    state::SurrealDb,
+    storage::Storage,
+    userid_extractor::AdminUserId,
+};
+
+use super::{
+    file,
+    pagination::{page_response, take_page, Pagination, SortOrder},
+    shortcut, Route,
+};
+
+pub fn routes() -> Vec<Route> {
+    [users::routes(), lists::routes(), objects::routes()].concat()
+}
+
+/// Kinds of objects admins can delete and transfer
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ObjectKind {
+    Link,
+    Shortcut,
+    File,
+    Paste,
+}
+
+impl ObjectKind {
+    fn table(self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Shortcut => "shortcut",
+            Self::File => "file",
+            Self::Paste => "paste",
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct TransferBody {
+    /// The id of the user who gets the objects
+    to: String,
+}
+
+/// Makes `to` the owner of `objects`, keeping the time they were created at
+async fn transfer(db: &SurrealDb, objects: Vec<RecordId>, to: &RecordId) -> Result<()> {
+    db.query(
+        "
+            BEGIN;
+            LET $edges = DELETE created WHERE out INSIDE $objects RETURN BEFORE;
+            FOR $edge IN $edges {
+                LET $object = $edge.out;
+                RELATE $to->created->$object CONTENT { timestamp: $edge.timestamp };
+            };
+            COMMIT;
+        ",
+    )
+    .bind(("objects", objects))
+    .bind(("to", to.clone()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Returns whether a user with the given id exists
+async fn user_exists(db: &SurrealDb, user: &RecordId) -> Result<bool> {
+    Ok(db
+        .query("SELECT VALUE id FROM ONLY $user")
+        .bind(("user", user.clone()))
+        .await?
+        .take::<Option<RecordId>>(0)?
+        .is_some())
+}
+
+mod users {
+    use super::*;
+
+    const PATH: &str = "/api/admin/user";
+
+    pub fn routes() -> Vec<Route> {
+        [
+            vec![(
+                RouteType::OpenApi(routes!(get_user_list)).scoped(RouteScope::Resource("admin")),
+                true,
+            )],
+            by_id::routes(),
+        ]
+        .concat()
+    }
+
+    /// A user along with what they own
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct AdminUserResponse {
+        #[schema(value_type = String)]
+        #[serde(serialize_with = "serialize_recordid_as_key")]
+        id: RecordId,
+        subject: String,
+        name: String,
+        email: Option<String>,
+
+        #[serde(default)]
+        groups: Vec<String>,
+
+        #[schema(value_type = Option<String>)]
+        last_login: Option<Datetime>,
+
+        links: u64,
+        shortcuts: u64,
+        files: u64,
+        pastes: u64,
+
+        /// Total visits of the user's shortcuts
+        visits: u64,
+
+        /// Total size of the user's files, in bytes
+        storage_used: u64,
+    }
+
+    const USER_FIELDS: &str = "id, subject, name, email, groups ?? [] AS groups, last_login, array::len(->created->link) AS links, array::len(->created->shortcut) AS shortcuts, array::len(->created->file) AS files, array::len(->created->paste) AS pastes, math::sum(->created->shortcut.visits) AS visits, math::sum(->created->file.size) AS storage_used";
+
+    /// Get all users with statistics about what they own
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        responses(
+            (status = OK, description = "Success", body = Vec<AdminUserResponse>)
+        )
+    )]
+    async fn get_user_list(
+        State(db): State<SurrealDb>,
+        _admin: AdminUserId,
+    ) -> AxumResult<Json<Vec<AdminUserResponse>>> {
+        Ok(Json(
+            db.query(format!("SELECT {USER_FIELDS} FROM user ORDER BY name"))
+                .await?
+                .take(0)?,
+        ))
+    }
+
+    mod by_id {
+        use super::*;
+
+        const PATH: &str = "/api/admin/user/{id}";
+
+        pub fn routes() -> Vec<Route> {
+            [
+                vec![(
+                    RouteType::OpenApi(routes!(get_user)).scoped(RouteScope::Resource("admin")),
+                    true,
+                )],
+                transfer::routes(),
+            ]
+            .concat()
+        }
+
+        /// Get a user with statistics about what they own
+        #[utoipa::path(
+            method(get),
+            path = PATH,
+            params(
+                ("id", description = "The id of the user")
+            ),
+            responses(
+                (status = OK, description = "Success", body = AdminUserResponse)
+            )
+        )]
+        async fn get_user(
+            State(db): State<SurrealDb>,
+            _admin: AdminUserId,
+            Path(id): Path<String>,
+        ) -> AxumResult<impl IntoResponse> {
+            let id = RecordId::from_table_key("user", id);
+
+            match db
+                .query(format!("SELECT {USER_FIELDS} FROM ONLY $user"))
+                .bind(("user", id))
+                .await?
+                .take::<Option<AdminUserResponse>>(0)?
+            {
+                Some(user) => Ok(Json(user).into_response()),
+                None => Ok((StatusCode::NOT_FOUND, "User not found").into_response()),
+            }
+        }
+
+        mod transfer {
+            use super::*;
+
+            const PATH: &str = "/api/admin/user/{id}/transfer";
+
+            pub fn routes() -> Vec<Route> {
+                vec![(
+                    RouteType::OpenApi(routes!(post_user_transfer))
+                        .scoped(RouteScope::Resource("admin")),
+                    true,
+                )]
+            }
+
+            /// Transfer everything a user owns to another user
+            ///
+            /// API tokens and unfinished uploads aren't transferred, so the user can still resume their uploads.
+            #[utoipa::path(
+                method(post),
+                path = PATH,
+                params(
+                    ("id", description = "The id of the user whose objects are transferred")
+                ),
+                request_body = TransferBody,
+                responses(
+                    (status = OK, description = "Success", body = str)
+                )
+            )]
+            async fn post_user_transfer(
+                State(db): State<SurrealDb>,
+                _admin: AdminUserId,
+                Path(id): Path<String>,
+                Json(body): Json<TransferBody>,
+            ) -> AxumResult<impl IntoResponse> {
+                let from = RecordId::from_table_key("user", id);
+                let to = RecordId::from_table_key("user", body.to);
+
+                if !user_exists(&db, &from).await? || !user_exists(&db, &to).await? {
+                    return Ok((StatusCode::NOT_FOUND, "User not found").into_response());
+                }
+
+                let objects: Vec<RecordId> = db
+                    .query("SELECT VALUE out FROM $user->created WHERE record::tb(out) NOT INSIDE ['token', 'upload']")
+                    .bind(("user", from))
+                    .await?
+                    .take(0)?;
+
+                let count = objects.len();
+                transfer(&db, objects, &to).await?;
+
+                Ok(format!("Transferred {count} objects").into_response())
+            }
+        }
+    }
+}
+
+mod lists {
+    use super::*;
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    struct AdminListParams {
+        /// Words to search for. Links are searched by URL, title, description and notes, and
+        /// shortcuts by their short link.
+        q: Option<String>,
+
+        /// Only return objects owned by the user with this id.
+        user: Option<String>,
+
+        /// The sort order by creation time. Defaults to newest first.
+        #[param(inline)]
+        order: Option<SortOrder>,
+
+        /// How many objects to return, at most 1000. Defaults to 50.
+        limit: Option<u32>,
+
+        /// Where the page starts, taken from the `Link` header of the previous page.
+        cursor: Option<String>,
+    }
+
+    impl AdminListParams {
+        fn pagination(&self) -> Result<Pagination, &'static str> {
+            Pagination::new(
+                self.limit,
+                self.cursor.as_deref(),
+                self.order.unwrap_or(SortOrder::Desc),
+            )
+        }
+
+        fn search(&self) -> Option<String> {
+            self.q.clone().filter(|q| !q.trim().is_empty())
+        }
+
+        fn user(&self) -> Option<RecordId> {
+            self.user
+                .clone()
+                .map(|user| RecordId::from_table_key("user", user))
+        }
+    }
+
+    const CREATED_KEY: &str = "time::nano((<-created.timestamp)[0])";
+
+    pub fn routes() -> Vec<Route> {
+        vec![
+            (
+                RouteType::OpenApi(routes!(get_link_list)).scoped(RouteScope::Resource("admin")),
+                true,
+            ),
+            (
+                RouteType::OpenApi(routes!(get_shortcut_list))
+                    .scoped(RouteScope::Resource("admin")),
+                true,
+            ),
+        ]
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct AdminLinkResponse {
+        #[schema(value_type = String)]
+        #[serde(serialize_with = "serialize_recordid_as_key")]
+        id: RecordId,
+        url: String,
+        title: Option<String>,
+
+        #[serde(default)]
+        tags: Vec<String>,
+        shortcuts: Vec<String>,
+
+        /// The id of the user who owns the link
+        #[schema(value_type = Option<String>)]
+        #[serde(default, serialize_with = "serialize_optional_recordid_as_key")]
+        owner: Option<RecordId>,
+        owner_name: Option<String>,
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct AdminShortcutResponse {
+        #[schema(value_type = String)]
+        #[serde(serialize_with = "serialize_recordid_as_key")]
+        id: RecordId,
+        shortlink: String,
+        visits: u64,
+
+        /// The ids of the links, files or pastes the shortcut expands to
+        #[schema(value_type = Vec<String>)]
+        #[serde(serialize_with = "serialize_recordid_vec_as_key")]
+        objects: Vec<RecordId>,
+
+        /// The id of the user who owns the shortcut
+        #[schema(value_type = Option<String>)]
+        #[serde(default, serialize_with = "serialize_optional_recordid_as_key")]
+        owner: Option<RecordId>,
+        owner_name: Option<String>,
+    }
+
+    fn serialize_optional_recordid_as_key<S>(
+        id: &Option<RecordId>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match id {
+            Some(id) => serialize_recordid_as_key(id, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Get everyone's links
+    ///
+    /// The links are paginated. If there are more, the `Link` header contains the URL of the next page with `rel="next"`.
+    #[utoipa::path(
+        method(get),
+        path = "/api/admin/link",
+        params(AdminListParams),
+        responses(
+            (status = OK, description = "Success", body = Vec<AdminLinkResponse>)
+        )
+    )]
+    async fn get_link_list(
+        State(db): State<SurrealDb>,
+        _admin: AdminUserId,
+        OriginalUri(uri): OriginalUri,
+        Query(params): Query<AdminListParams>,
+    ) -> AxumResult<Response> {
+        let pagination = match params.pagination() {
+            Ok(pagination) => pagination,
+            Err(message) => return Ok((StatusCode::BAD_REQUEST, message).into_response()),
+        };
+
+        let mut conditions = vec![];
+        if params.search().is_some() {
+            conditions.push("(url @0@ $search OR title @1@ $search OR description @2@ $search OR notes @3@ $search)");
+        }
+        if params.user().is_some() {
+            conditions.push("array::any(array::matches(<-created<-user.id, $user))");
+        }
+
+        let (links, next) = take_page::<AdminLinkResponse>(
+            db.query(pagination.query(
+                "id, url, title, tags, <-expands_to<-shortcut.shortlink AS shortcuts, array::first(<-created<-user.id) AS owner, array::first(<-created<-user.name) AS owner_name",
+                "link",
+                &conditions,
+                CREATED_KEY,
+            ))
+            .bind(("search", params.search()))
+            .bind(("user", params.user()))
+            .bind(("cursor", pagination.cursor))
+            .await?,
+            pagination.limit,
+        )?;
+
+        Ok(page_response(&uri, links, next)?)
+    }
+
+    /// Get everyone's shortcuts
+    ///
+    /// The shortcuts are paginated. If there are more, the `Link` header contains the URL of the next page with `rel="next"`.
+    #[utoipa::path(
+        method(get),
+        path = "/api/admin/shortcut",
+        params(AdminListParams),
+        responses(
+            (status = OK, description = "Success", body = Vec<AdminShortcutResponse>)
+        )
+    )]
+    async fn get_shortcut_list(
+        State(db): State<SurrealDb>,
+        _admin: AdminUserId,
+        OriginalUri(uri): OriginalUri,
+        Query(params): Query<AdminListParams>,
+    ) -> AxumResult<Response> {
+        let pagination = match params.pagination() {
+            Ok(pagination) => pagination,
+            Err(message) => return Ok((StatusCode::BAD_REQUEST, message).into_response()),
+        };
+
+        let mut conditions = vec![];
+        if params.search().is_some() {
+            conditions.push("string::contains(shortlink, string::lowercase($search))");
+        }
+        if params.user().is_some() {
+            conditions.push("array::any(array::matches(<-created<-user.id, $user))");
+        }
+
+        let (shortcuts, next) = take_page::<AdminShortcutResponse>(
+            db.query(pagination.query(
+                "id, shortlink, visits, ->expands_to.out AS objects, array::first(<-created<-user.id) AS owner, array::first(<-created<-user.name) AS owner_name",
+                "shortcut",
+                &conditions,
+                CREATED_KEY,
+            ))
+            .bind(("search", params.search()))
+            .bind(("user", params.user()))
+            .bind(("cursor", pagination.cursor))
+            .await?,
+            pagination.limit,
+        )?;
+
+        Ok(page_response(&uri, shortcuts, next)?)
+    }
+}
+
+mod objects {
+    use super::*;
+
+    const PATH: &str = "/api/admin/{kind}/{id}";
+
+    pub fn routes() -> Vec<Route> {
+        [
+            vec![(
+                RouteType::OpenApi(routes!(delete_object)).scoped(RouteScope::Resource("admin")),
+                true,
+            )],
+            transfer::routes(),
+        ]
+        .concat()
+    }
+
+    /// Delete anyone's link, shortcut, file or paste
+    ///
+    /// Deleting a link, file or paste deletes all shortcuts pointing to it too.
+    #[utoipa::path(
+        method(delete),
+        path = PATH,
+        params(
+            ("kind" = ObjectKind, Path, description = "What kind of object to delete"),
+            ("id", description = "The id of the object to delete")
+        ),
+        responses(
+            (status = OK, description = "Success", body = str)
+        )
+    )]
+    async fn delete_object(
+        State(db): State<SurrealDb>,
+        State(storage): State<Storage>,
+        _admin: AdminUserId,
+        Path((kind, id)): Path<(ObjectKind, String)>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key(kind.table(), id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $object")
+            .bind(("object", id.clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Object not found").into_response());
+        }
+
+        match kind {
+            ObjectKind::File => file::delete_file(&db, &storage, &id).await?,
+            ObjectKind::Link | ObjectKind::Paste => shortcut::delete_object(&db, &id).await?,
+            ObjectKind::Shortcut => {
+                db.query(
+                    "
+                        BEGIN;
+                        fn::archive_changes([$shortcut]);
+                        DELETE $shortcut<-created;
+                        DELETE $shortcut<-changed;
+                        DELETE $shortcut<-owns;
+                        DELETE $shortcut->expands_to;
+                        DELETE (SELECT VALUE array::flatten([->logged, ->logged->visit]) FROM ONLY $shortcut);
+                        DELETE $shortcut;
+                        COMMIT;
+                    ",
+                )
+                .bind(("shortcut", id))
+                .await?
+                .check()?;
+            }
+        }
+
+        Ok("Object deleted successfully".into_response())
+    }
+
+    mod transfer {
+        use super::*;
+
+        const PATH: &str = "/api/admin/{kind}/{id}/transfer";
+
+        pub fn routes() -> Vec<Route> {
+            vec![(
+                RouteType::OpenApi(routes!(post_object_transfer))
+                    .scoped(RouteScope::Resource("admin")),
+                true,
+            )]
+        }
+
+        /// Transfer anyone's link, shortcut, file or paste to another user
+        ///
+        /// The shortcuts pointing to a link, file or paste are transferred along with it.
+        #[utoipa::path(
+            method(post),
+            path = PATH,
+            params(
+                ("kind" = ObjectKind, Path, description = "What kind of object to transfer"),
+                ("id", description = "The id of the object to transfer")
+            ),
+            request_body = TransferBody,
+            responses(
+                (status = OK, description = "Success", body = str)
+            )
+        )]
+        async fn post_object_transfer(
+            State(db): State<SurrealDb>,
+            _admin: AdminUserId,
+            Path((kind, id)): Path<(ObjectKind, String)>,
+            Json(body): Json<TransferBody>,
+        ) -> AxumResult<impl IntoResponse> {
+            let id = RecordId::from_table_key(kind.table(), id);
+            let to = RecordId::from_table_key("user", body.to);
+
+            if !user_exists(&db, &to).await? {
+                return Ok((StatusCode::NOT_FOUND, "User not found").into_response());
+            }
+
+            let Some(objects) = db
+                .query(
+                    "SELECT VALUE array::concat([id], <-expands_to<-shortcut.id) FROM ONLY $object",
+                )
+                .bind(("object", id))
+                .await?
+                .take::<Option<Vec<RecordId>>>(0)?
+            else {
+                return Ok((StatusCode::NOT_FOUND, "Object not found").into_response());
+            };
+
+            transfer(&db, objects, &to).await?;
+
+            Ok("Object transferred successfully".into_response())
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::{ops::Deref, sync::atomic::Ordering};
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Multipart, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{TimeDelta, Utc};
+use color_eyre::{
+    eyre::{eyre, OptionExt},
+    Result,
+};
+use futures::{StreamExt as _, TryStreamExt as _};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::distr::{Alphanumeric, SampleString as _};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Datetime, RecordId};
+use tracing::warn;
+use utoipa::ToSchema;
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::RouteScope,
+    axum_error::AxumResult,
+    images::{self, remove_thumbnails},
+    quota,
+    routes::RouteType,
+    schema::{Created, File, PartialCreated, PartialFile, ShortcutOptions},
+    serialize_recordid::serialize_recordid_as_key,
+    settings::{ArcSettings, Files},
+    state::SurrealDb,
+    storage::Storage,
+    userid_extractor::SessionUserId,
+};
+
+use super::{
+    shortcut::{self, PostShortcutOptions},
+    Route,
+};
+
+const PATH: &str = "/api/file";
+
+pub fn routes() -> Vec<Route> {
+    let (schemas, paths, router) = routes!(get_file_list, post_file_list);
+
+    [
+        vec![(
+            RouteType::OpenApi((schemas, paths, router.layer(DefaultBodyLimit::disable())))
+                .scoped(RouteScope::Resource("file")),
+            true,
+        )],
+        by_id::routes(),
+    ]
+    .concat()
+}
+
+/// Returns the storage key of the content of the file with the given id
+pub fn blob_key(id: &RecordId) -> String {
+    id.key().to_string()
+}
+
+/// Works out when an upload should expire, given the lifetime (in seconds) requested by the uploader
+pub fn expiry(settings: &Files, requested: Option<u64>) -> Option<Datetime> {
+    let lifetime = match (
+        requested.or(settings.default_lifetime),
+        settings.max_lifetime,
+    ) {
+        (Some(lifetime), Some(max_lifetime)) => lifetime.min(max_lifetime),
+        (Some(lifetime), None) | (None, Some(lifetime)) => lifetime,
+        (None, None) => return None,
+    };
+
+    Utc::now()
+        .checked_add_signed(TimeDelta::try_seconds(lifetime.try_into().ok()?)?)
+        .map(Datetime::from)
+}
+
+/// Checks whether a file has expired but hasn't been deleted yet
+pub async fn is_expired(db: &SurrealDb, id: &RecordId) -> Result<bool> {
+    Ok(db
+        .query("SELECT VALUE expires_at != NONE AND expires_at < time::now() FROM ONLY $file")
+        .bind(("file", id.clone()))
+        .await?
+        .take::<Option<bool>>(0)?
+        .unwrap_or(false))
+}
+
+/// Deletes a file along with its shortcuts and content, regardless of who owns it
+pub async fn delete_file(db: &SurrealDb, storage: &Storage, id: &RecordId) -> Result<()> {
+    let thumbnails = thumbnail_sizes(db, id).await?;
+
+    shortcut::delete_object(db, id).await?;
+
+    remove_blob(storage, &blob_key(id)).await;
+    remove_thumbnails(storage, &blob_key(id), &thumbnails).await;
+
+    Ok(())
+}
+
+/// Returns the sizes of the thumbnails generated for a file
+pub async fn thumbnail_sizes(db: &SurrealDb, id: &RecordId) -> Result<Vec<u32>> {
+    Ok(db
+        .query("SELECT VALUE thumbnails FROM ONLY $file")
+        .bind(("file", id.clone()))
+        .await?
+        .take::<Option<Vec<u32>>>(0)?
+        .unwrap_or_default())
+}
+
+/// Parses a boolean form field or metadata value
+pub fn parse_flag(value: &str) -> bool {
+    matches!(value.trim(), "true" | "1" | "on")
+}
+
+/// Characters that can be sent as they are in the `filename*` parameter of RFC 5987
+const FILENAME_ATTR_CHARS: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Whether browsers only display content of this type, without running any scripts in it.
+///
+/// Files are served from the same origin as the dashboard, so everything else is downloaded instead
+/// of being shown.
+fn is_passive_content_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match essence.split_once('/') {
+        // SVG images can contain scripts
+        Some(("image", subtype)) => {
+            !subtype.is_empty() && !subtype.contains("svg") && !subtype.contains("xml")
+        }
+        Some(("video" | "audio", subtype)) => !subtype.is_empty() && !subtype.contains("xml"),
+        _ => essence == "application/pdf",
+    }
+}
+
+/// Builds a `Content-Disposition` header with the name of a file, as a plain ASCII fallback and
+/// percent-encoded as UTF-8
+fn content_disposition(disposition: &str, name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '%' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        utf8_percent_encode(name, FILENAME_ATTR_CHARS)
+    )
+}
+
+/// Streams the content of a file as a response
+///
+/// Only images, videos, audio and PDFs are shown in the browser, everything else is downloaded.
+/// The content is sandboxed either way, so that it can't run scripts on this origin.
+pub async fn file_response(storage: &Storage, file: File) -> AxumResult<Response> {
+    let blob = storage.get(&blob_key(&file.id)).await?;
+
+    let disposition = if is_passive_content_type(&file.content_type) {
+        "inline"
+    } else {
+        "attachment"
+    };
+
+    let content_type = HeaderValue::from_str(&file.content_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, HeaderValue::from(file.size)),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&content_disposition(disposition, &file.name))?,
+            ),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("sandbox"),
+            ),
+        ],
+        Body::from_stream(blob),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct GetFileResponse {
+    #[schema(value_type = String)]
+    #[serde(serialize_with = "serialize_recordid_as_key")]
+    pub id: RecordId,
+    pub shortcuts: Vec<String>,
+    pub name: String,
+    pub content_type: String,
+    pub size: u64,
+
+    #[schema(value_type = Option<String>)]
+    pub expires_at: Option<Datetime>,
+
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    /// Sizes of the available thumbnails, which can be downloaded from `/{shortcut}/thumbnail/{size}`
+    pub thumbnails: Vec<u32>,
+}
+
+/// Get all files you have access to
+#[utoipa::path(
+    method(get),
+    path = PATH,
+    responses(
+        (status = OK, description = "Success", body = Vec<GetFileResponse>)
+    )
+)]
+async fn get_file_list(
+    State(db): State<SurrealDb>,
+    userid: SessionUserId,
+) -> AxumResult<Json<Vec<GetFileResponse>>> {
+    Ok(Json(
+        db.query(
+            "SELECT VALUE ->created->file.{id, name, content_type, size, expires_at, width, height, thumbnails, shortcuts: <-expands_to<-shortcut.shortlink} FROM ONLY $user",
+        )
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take(0)?,
+    ))
+}
+
+/// Upload a new file
+#[utoipa::path(
+    method(post),
+    path = PATH,
+    request_body(content = PostFileBody, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Success", body = GetFileResponse),
+        (status = PAYLOAD_TOO_LARGE, description = "The file exceeds your quota", body = str),
+        (status = UNPROCESSABLE_ENTITY, description = "The metadata of the image couldn't be stripped", body = str)
+    )
+)]
+async fn post_file_list(
+    State(db): State<SurrealDb>,
+    State(storage): State<Storage>,
+    State(settings): State<ArcSettings>,
+    userid: SessionUserId,
+    mut multipart: Multipart,
+) -> AxumResult<impl IntoResponse> {
+    let id = RecordId::from_table_key("file", Alphanumeric.sample_string(&mut rand::rng(), 20));
+    let key = blob_key(&id);
+
+    let max_upload_size = quota::usage(&db, &settings.quotas, &userid)
+        .await?
+        .max_upload_size();
+
+    let mut shortcuts = Vec::new();
+    let mut expires_in = None;
+    let mut keep_metadata = false;
+    let mut options = PostShortcutOptions::default();
+    let mut upload = None;
+
+    while let Some(field) = multipart.next_field().await? {
+        match field.name().map(str::to_string).as_deref() {
+            Some("shortcuts") => shortcuts.push(field.text().await?),
+            Some("expires_in") => match field.text().await?.trim().parse() {
+                Ok(seconds) => expires_in = Some(seconds),
+                Err(_) => {
+                    if upload.is_some() {
+                        remove_blob(&storage, &key).await;
+                    }
+                    return Ok((StatusCode::BAD_REQUEST, "Invalid expires_in").into_response());
+                }
+            },
+            Some("keep_metadata") => keep_metadata = parse_flag(&field.text().await?),
+            Some(name) if PostShortcutOptions::FIELDS.contains(&name) => {
+                if let Err(message) = options.set_field(name, &field.text().await?) {
+                    if upload.is_some() {
+                        remove_blob(&storage, &key).await;
+                    }
+                    return Ok((StatusCode::BAD_REQUEST, message).into_response());
+                }
+            }
+            Some("file") if upload.is_none() => {
+                let name = field.file_name().unwrap_or("file").to_string();
+                let content_type = field
+                    .content_type()
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+
+                let (body, exceeded) = quota::limit_stream(
+                    field.map_err(std::io::Error::other).boxed(),
+                    max_upload_size,
+                );
+                let size = match storage.put(&key, body).await {
+                    Ok(size) => size,
+                    Err(e) => {
+                        remove_blob(&storage, &key).await;
+
+                        return match max_upload_size {
+                            Some(limit) if exceeded.load(Ordering::Relaxed) => Ok((
+                                StatusCode::PAYLOAD_TOO_LARGE,
+                                quota::exceeded_message(limit),
+                            )
+                                .into_response()),
+                            _ => Err(e.into()),
+                        };
+                    }
+                };
+
+                upload = Some(PartialFile {
+                    name,
+                    content_type,
+                    size,
+                    expires_at: None,
+                    width: None,
+                    height: None,
+                    thumbnails: Vec::new(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let Some(mut upload) = upload else {
+        return Ok((StatusCode::BAD_REQUEST, "Missing file").into_response());
+    };
+
+    let options = match options.into_options().await {
+        Ok(options) => options,
+        Err(e) => {
+            remove_blob(&storage, &key).await;
+            return Err(e.into());
+        }
+    };
+
+    upload.expires_at = expiry(&settings.files, expires_in);
+    if let Err(message) =
+        images::process_upload(&storage, &settings.images, &key, &mut upload, keep_metadata).await
+    {
+        remove_blob(&storage, &key).await;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, message).into_response());
+    }
+
+    if shortcuts.is_empty() {
+        shortcuts.push(shortcut::random_shortlink());
+    }
+
+    let collisions = shortcut::find_collisions(&db, &shortcuts).await?;
+
+    if !collisions.is_empty() {
+        remove_blob(&storage, &key).await;
+        remove_thumbnails(&storage, &key, &upload.thumbnails).await;
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("Shortcuts already exist: {}", collisions.join(", ")),
+        )
+            .into_response());
+    }
+
+    let thumbnails = upload.thumbnails.clone();
+    let created_file = match create_file(&db, &userid, id, upload, &shortcuts, &options).await {
+        Ok(file) => file,
+        Err(e) => {
+            remove_blob(&storage, &key).await;
+            remove_thumbnails(&storage, &key, &thumbnails).await;
+            return Err(e.into());
+        }
+    };
+
+    Ok(Json(db.query(
+            "SELECT id, name, content_type, size, expires_at, width, height, thumbnails, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $file WHERE array::any(array::matches(<-created<-user.id, $user))",
+        )
+        .bind(("file", created_file.id))
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take::<Option<GetFileResponse>>(0)?.ok_or_eyre("Failed to create file")?
+        ).into_response())
+}
+
+/// Creates a file record owned by `user` along with shortcuts to it.
+///
+/// The content of the file must already be stored under [`blob_key`] of `id`.
+pub async fn create_file(
+    db: &SurrealDb,
+    user: &RecordId,
+    id: RecordId,
+    file: PartialFile,
+    shortcuts: &[String],
+    options: &ShortcutOptions,
+) -> Result<File> {
+    let created_file: File = db
+        .create(id)
+        .content(file)
+        .await?
+        .ok_or_eyre("Failed to create file")?;
+
+    let file_created_rel: Vec<Created> = db
+        .insert("created")
+        .relation(PartialCreated {
+            object: created_file.id.clone(),
+            user: user.clone(),
+        })
+        .await?;
+
+    if file_created_rel.is_empty() {
+        let _: Option<File> = db.delete(&created_file.id).await?;
+        return Err(eyre!("Failed to create file"));
+    }
+
+    shortcut::create_shortcuts(db, user, &created_file.id, shortcuts, options).await?;
+
+    Ok(created_file)
+}
+
+pub async fn remove_blob(storage: &Storage, key: &str) {
+    if let Err(e) = storage.delete(key).await {
+        warn!(error = ?e, key, "Failed to remove file blob");
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PostFileBody {
+    /// The file to upload.
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+
+    /// The short URLs to create for this file. Leave empty to get 1 random 10-character shortcut.
+    shortcuts: Option<Vec<String>>,
+
+    /// After how many seconds the file should be deleted. Capped by the server's maximum lifetime.
+    expires_in: Option<u64>,
+
+    /// Set to `true` to keep the EXIF metadata (e.g. camera model and GPS location) of uploaded images. It's stripped by default,
+    /// and images it can't be stripped from are refused.
+    keep_metadata: Option<bool>,
+
+    #[serde(flatten)]
+    options: PostShortcutOptions,
+}
+
+mod by_id {
+    use axum::{extract::Path, http::StatusCode, response::IntoResponse};
+
+    use super::*;
+
+    const PATH: &str = "/api/file/{id}";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(get_file, delete_file)).scoped(RouteScope::Resource("file")),
+            true,
+        )]
+    }
+
+    /// Get the metadata of a specific file by id
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        params(
+            ("id", description = "The id of the file to get")
+        ),
+        responses(
+            (status = OK, description = "Success", body = GetFileResponse)
+        )
+    )]
+    async fn get_file(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("file", id);
+
+        match db.query(
+            "SELECT id, name, content_type, size, expires_at, width, height, thumbnails, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $file WHERE array::any(array::matches(<-created<-user.id, $user))",
+        )
+        .bind(("file", id))
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take::<Option<GetFileResponse>>(0)? {
+            Some(file) => Ok(Json(file).into_response()),
+            None => Ok((StatusCode::NOT_FOUND, "File not found").into_response()),
+        }
+    }
+
+    /// Delete a file and all shortcuts pointing to it
+    #[utoipa::path(
+        method(delete),
+        path = PATH,
+        params(
+            ("id", description = "The id of the file to delete")
+        ),
+        responses(
+            (status = OK, description = "Success", body = str)
+        )
+    )]
+    async fn delete_file(
+        State(db): State<SurrealDb>,
+        State(storage): State<Storage>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("file", id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $file WHERE array::any(array::matches(<-created<-user.id, $user))")
+            .bind(("file", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "File not found").into_response());
+        }
+
+        super::delete_file(&db, &storage, &id).await?;
+
+        Ok(("File deleted successfully").into_response())
+    }
+}
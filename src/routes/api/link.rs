@@ -1,33 +1,42 @@
 use std::ops::Deref;
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::{eyre, OptionExt};
-use rand::distr::{Alphanumeric, SampleString as _};
 use serde::{Deserialize, Serialize};
-use surrealdb::RecordId;
-use utoipa::ToSchema;
+use surrealdb::{Datetime, RecordId};
+use utoipa::{IntoParams, ToSchema};
 use utoipa_axum::routes;
 
 use crate::{
+    api_token::RouteScope,
     axum_error::AxumResult,
+    groups,
     routes::RouteType,
-    schema::{
-        Created, ExpandsTo, Link, PartialCreated, PartialExpandsTo, PartialLink, PartialShortcut,
-        Shortcut,
-    },
+    schema::{Created, Link, PartialCreated, PartialLink},
     serialize_recordid::serialize_recordid_as_key,
     state::SurrealDb,
     userid_extractor::SessionUserId,
 };
 
-use super::Route;
+use super::{
+    pagination::{page_response, take_page, Pagination, SortOrder},
+    shortcut::{self, GetHistoryEntry, PostShortcutOptions},
+    Route,
+};
 
 const PATH: &str = "/api/link";
 
 pub fn routes() -> Vec<Route> {
     [
         vec![(
-            RouteType::OpenApi(routes!(get_link_list, post_link_list)),
+            RouteType::OpenApi(routes!(get_link_list, post_link_list))
+                .scoped(RouteScope::Resource("link")),
             true,
         )],
         by_id::routes(),
@@ -42,28 +51,197 @@ pub struct GetLinkResponse {
     pub id: RecordId,
     pub shortcuts: Vec<String>,
     pub url: String,
+    pub fallback_url: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub notes: Option<String>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// The OIDC group whose members can manage the link along with its creator
+    pub owner_group: Option<String>,
+}
+
+/// Trims and lowercases tags, and drops empty and duplicate ones
+pub fn normalize_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    normalized
+}
+
+/// Returns an error message for the first URL that the database would refuse to store
+fn invalid_url<'a>(urls: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    urls.into_iter()
+        .find(|url| url::Url::parse(url).is_err())
+        .map(|url| format!("Invalid URL: {url}"))
+}
+
+/// What the link list is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum LinkSort {
+    /// When the link was created
+    Created,
+    Url,
+
+    /// The total number of visits of all shortcuts to the link
+    Visits,
+
+    /// How well the link matches the search, only available when searching
+    Relevance,
+}
+
+impl LinkSort {
+    /// SurrealQL expression giving the sort key of a link
+    fn key(self) -> &'static str {
+        match self {
+            Self::Created => "time::nano((<-created.timestamp)[0])",
+            Self::Url => "url",
+            Self::Visits => "math::sum(<-expands_to<-shortcut.visits)",
+            // search::score is NONE for fields that didn't match
+            Self::Relevance => "(search::score(0) ?? 0) + (search::score(1) ?? 0) + (search::score(2) ?? 0) + (search::score(3) ?? 0)",
+        }
+    }
+
+    fn default_order(self) -> SortOrder {
+        match self {
+            Self::Url => SortOrder::Asc,
+            Self::Created | Self::Visits | Self::Relevance => SortOrder::Desc,
+        }
+    }
 }
 
-/// Get all links you have access to
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetLinkListParams {
+    /// Words to search for in the URL, title, description and notes of the links.
+    q: Option<String>,
+
+    /// Comma-separated tags, all of which the links need to have.
+    tags: Option<String>,
+
+    /// Only return links created after this RFC 3339 timestamp.
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_after: Option<DateTime<Utc>>,
+
+    /// Only return links created before this RFC 3339 timestamp.
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_before: Option<DateTime<Utc>>,
+
+    /// Only return links that have shortcuts (`true`) or that don't (`false`).
+    has_shortcut: Option<bool>,
+
+    /// What to sort the links by. Defaults to `relevance` when searching and `created` otherwise.
+    #[param(inline)]
+    sort: Option<LinkSort>,
+
+    /// The sort order. Defaults to ascending for `url` and descending for everything else.
+    #[param(inline)]
+    order: Option<SortOrder>,
+
+    /// How many links to return, at most 1000. Defaults to 50.
+    limit: Option<u32>,
+
+    /// Where the page starts, taken from the `Link` header of the previous page.
+    cursor: Option<String>,
+}
+
+/// Get the links you have access to
+///
+/// The links are paginated. If there are more, the `Link` header contains the URL of the next page with `rel="next"`.
 #[utoipa::path(
     method(get),
     path = PATH,
+    params(GetLinkListParams),
     responses(
-        (status = OK, description = "Success", body = Vec<GetLinkResponse>)
+        (status = OK, description = "Success", body = Vec<GetLinkResponse>),
+        (status = BAD_REQUEST, description = "The cursor is invalid, or sorting by relevance without searching", body = str)
     )
 )]
 async fn get_link_list(
     State(db): State<SurrealDb>,
     userid: SessionUserId,
-) -> AxumResult<Json<Vec<GetLinkResponse>>> {
-    Ok(Json(
-        db.query(
-            "SELECT VALUE ->created->link.{id, url, shortcuts: <-expands_to<-shortcut.shortlink} FROM ONLY $user",
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<GetLinkListParams>,
+) -> AxumResult<Response> {
+    let search = params.q.filter(|q| !q.trim().is_empty());
+    let tags = normalize_tags(params.tags.as_deref().unwrap_or_default().split(','));
+
+    let sort = params.sort.unwrap_or(if search.is_some() {
+        LinkSort::Relevance
+    } else {
+        LinkSort::Created
+    });
+
+    if sort == LinkSort::Relevance && search.is_none() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "Sorting by relevance needs a search",
         )
+            .into_response());
+    }
+
+    let pagination = match Pagination::new(
+        params.limit,
+        params.cursor.as_deref(),
+        params.order.unwrap_or(sort.default_order()),
+    ) {
+        Ok(pagination) => pagination,
+        Err(message) => return Ok((StatusCode::BAD_REQUEST, message).into_response()),
+    };
+
+    // the full-text search needs the index of the table, so it goes through the table and keeps the accessible matches
+    let mut conditions = Vec::new();
+    let source = if search.is_some() {
+        conditions.push("id INSIDE $accessible");
+        conditions.push("(url @0@ $search OR title @1@ $search OR description @2@ $search OR notes @3@ $search)");
+        "link"
+    } else {
+        "$accessible"
+    };
+    if !tags.is_empty() {
+        conditions.push("tags CONTAINSALL $tags");
+    }
+    if params.created_after.is_some() {
+        conditions.push("(<-created.timestamp)[0] > $created_after");
+    }
+    if params.created_before.is_some() {
+        conditions.push("(<-created.timestamp)[0] < $created_before");
+    }
+    match params.has_shortcut {
+        Some(true) => conditions.push("array::len(<-expands_to) > 0"),
+        Some(false) => conditions.push("array::len(<-expands_to) == 0"),
+        None => {}
+    }
+
+    let (links, next) = take_page::<GetLinkResponse>(
+        db.query("LET $accessible = array::union($user->created->link, $user->member_of->oidc_group->owns->link);")
+        .query(pagination.query_from(
+            "id, url, fallback_url, title, description, notes, tags, <-expands_to<-shortcut.shortlink AS shortcuts, array::first(<-owns<-oidc_group.name) AS owner_group",
+            "link",
+            source,
+            &conditions,
+            sort.key(),
+        ))
         .bind(("user", userid.deref().clone()))
-        .await?
-        .take(0)?,
-    ))
+        .bind(("search", search))
+        .bind(("tags", tags))
+        .bind(("created_after", params.created_after.map(Datetime::from)))
+        .bind(("created_before", params.created_before.map(Datetime::from)))
+        .bind(("cursor", pagination.cursor))
+        .await?,
+        pagination.limit,
+    )?;
+
+    Ok(page_response(&uri, links, next)?)
 }
 
 /// Create a new link
@@ -72,7 +250,8 @@ async fn get_link_list(
     path = PATH,
     request_body = PostLinkBody,
     responses(
-        (status = OK, description = "Success", body = GetLinkResponse)
+        (status = OK, description = "Success", body = GetLinkResponse),
+        (status = BAD_REQUEST, description = "A URL is invalid, or a shortcut already exists", body = str)
     )
 )]
 async fn post_link_list(
@@ -80,17 +259,17 @@ async fn post_link_list(
     userid: SessionUserId,
     Json(body): Json<PostLinkBody>,
 ) -> AxumResult<impl IntoResponse> {
-    let shortcuts = body.shortcuts.unwrap_or_else(|| {
-        let mut rng = rand::rng();
-        let shortcut = Alphanumeric.sample_string(&mut rng, 10);
-        vec![shortcut]
-    });
+    if let Some(message) =
+        invalid_url(std::iter::once(body.url.as_str()).chain(body.fallback_url.as_deref()))
+    {
+        return Ok((StatusCode::BAD_REQUEST, message).into_response());
+    }
 
-    let collisions: Vec<String> = db
-        .query("SELECT VALUE shortlink FROM shortcut WHERE array::any(array::matches($shortcuts, shortlink))")
-        .bind(("shortcuts", shortcuts.clone()))
-        .await?
-        .take(0)?;
+    let shortcuts = body
+        .shortcuts
+        .unwrap_or_else(|| vec![shortcut::random_shortlink()]);
+
+    let collisions = shortcut::find_collisions(&db, &shortcuts).await?;
 
     if !collisions.is_empty() {
         return Ok((
@@ -100,9 +279,24 @@ async fn post_link_list(
             .into_response());
     }
 
+    let owner_group =
+        match shortcut::requested_owner_group(&db, &userid, body.owner_group.as_deref()).await? {
+            Ok(owner_group) => owner_group.flatten(),
+            Err(response) => return Ok(response),
+        };
+
+    let options = body.options.into_options().await?;
+
     let created_link: Link = db
         .create("link")
-        .content(PartialLink { url: body.url })
+        .content(PartialLink {
+            url: body.url,
+            fallback_url: body.fallback_url,
+            title: body.title,
+            description: body.description,
+            notes: body.notes,
+            tags: normalize_tags(body.tags.iter().map(String::as_str)),
+        })
         .await?
         .ok_or_eyre("Failed to create link")?;
 
@@ -119,58 +313,19 @@ async fn post_link_list(
         return Err(eyre!("Failed to create link").into());
     }
 
-    let created_shortcuts: Vec<Shortcut> = db
-        .insert("shortcut")
-        .content(
-            shortcuts
-                .iter()
-                .map(|shortcut| PartialShortcut {
-                    shortlink: shortcut.clone(),
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await?;
-
-    if created_shortcuts.len() != created_shortcuts.len() {
-        return Err(eyre!("Failed to create shortcuts").into());
-    }
+    let created_shortcuts =
+        shortcut::create_shortcuts(&db, &userid, &created_link.id, &shortcuts, &options).await?;
 
-    let shortcuts_created_rel: Vec<Created> = db
-        .insert("created")
-        .relation(
-            created_shortcuts
-                .iter()
-                .map(|shortcut| PartialCreated {
-                    object: shortcut.id.clone(),
-                    user: userid.deref().clone(),
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await?;
+    if owner_group.is_some() {
+        let objects: Vec<RecordId> = std::iter::once(created_link.id.clone())
+            .chain(created_shortcuts.into_iter().map(|shortcut| shortcut.id))
+            .collect();
 
-    if shortcuts_created_rel.len() != created_shortcuts.len() {
-        return Err(eyre!("Failed to create shortcuts").into());
-    }
-
-    let expands_to_rel: Vec<ExpandsTo> = db
-        .insert("expands_to")
-        .relation(
-            created_shortcuts
-                .iter()
-                .map(|shortcut| PartialExpandsTo {
-                    object: created_link.id.clone(),
-                    shortcut: shortcut.id.clone(),
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await?;
-
-    if expands_to_rel.len() != created_shortcuts.len() {
-        return Err(eyre!("Failed to create shortcuts").into());
+        groups::set_owner(&db, &objects, owner_group).await?;
     }
 
     Ok(Json(db.query(
-            "SELECT id, url, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $link WHERE array::any(array::matches(<-created<-user.id, $user))",
+            "SELECT id, url, fallback_url, title, description, notes, tags, <-expands_to<-shortcut.shortlink AS shortcuts, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $link WHERE fn::can_access(id, $user)",
         )
         .bind(("link", created_link.id))
         .bind(("user", userid.deref().clone()))
@@ -183,7 +338,32 @@ async fn post_link_list(
 struct PostLinkBody {
     /// The short URLs to create for this link. Set to `null` to get 1 random 10-character shortcut.
     shortcuts: Option<Vec<String>>,
+
+    /// Where the link leads.
+    ///
+    /// This can be a go-link template: `{1}`, `{2}`, ... are replaced with the path segments after the short link,
+    /// and other placeholders like `{query}` with the query parameter of the same name.
+    /// `https://jira.example.com/browse/{1}` makes `/{shortlink}/ABC-123` lead to `https://jira.example.com/browse/ABC-123`.
     url: String,
+
+    /// Where a go-link template leads when some of its placeholders don't get a value.
+    fallback_url: Option<String>,
+
+    title: Option<String>,
+    description: Option<String>,
+
+    /// Free-form notes, only visible to you.
+    notes: Option<String>,
+
+    /// Tags to find the link by. They are stored in lowercase.
+    #[serde(default)]
+    tags: Vec<String>,
+
+    /// An OIDC group you're a member of, whose members can manage the link and its shortcuts too.
+    owner_group: Option<String>,
+
+    #[serde(flatten)]
+    options: PostShortcutOptions,
 }
 
 mod by_id {
@@ -194,7 +374,15 @@ mod by_id {
     const PATH: &str = "/api/link/{id}";
 
     pub fn routes() -> Vec<Route> {
-        vec![(RouteType::OpenApi(routes!(get_link, delete_link)), true)]
+        [
+            vec![(
+                RouteType::OpenApi(routes!(get_link, patch_link, delete_link))
+                    .scoped(RouteScope::Resource("link")),
+                true,
+            )],
+            history::routes(),
+        ]
+        .concat()
     }
 
     /// Get a specific link by id
@@ -216,7 +404,7 @@ mod by_id {
         let id = RecordId::from_table_key("link", id);
 
         match db.query(
-            "SELECT id, url, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $link WHERE array::any(array::matches(<-created<-user.id, $user))",
+            "SELECT id, url, fallback_url, title, description, notes, tags, <-expands_to<-shortcut.shortlink AS shortcuts, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $link WHERE fn::can_access(id, $user)",
         )
         .bind(("link", id))
         .bind(("user", userid.deref().clone()))
@@ -227,7 +415,147 @@ mod by_id {
         }
     }
 
+    #[derive(Deserialize, ToSchema)]
+    struct PatchLinkBody {
+        /// The new URL of the link. The previous one is kept in the history of the link.
+        url: Option<String>,
+
+        /// The new fallback URL of the go-link template. Set to an empty string to remove it.
+        fallback_url: Option<String>,
+
+        /// The new title. Set to an empty string to remove it.
+        title: Option<String>,
+
+        /// The new description. Set to an empty string to remove it.
+        description: Option<String>,
+
+        /// The new notes. Set to an empty string to remove them.
+        notes: Option<String>,
+
+        /// The new tags, replacing the current ones.
+        tags: Option<Vec<String>>,
+
+        /// An OIDC group you're a member of, whose members can manage the link and its shortcuts too.
+        /// Set to an empty string to make them personal again.
+        owner_group: Option<String>,
+    }
+
+    /// Change a link
+    ///
+    /// All shortcuts pointing to the link lead to the new URL right away.
+    #[utoipa::path(
+        method(patch),
+        path = PATH,
+        params(
+            ("id", description = "The id of the link to change")
+        ),
+        request_body = PatchLinkBody,
+        responses(
+            (status = OK, description = "Success", body = GetLinkResponse),
+            (status = BAD_REQUEST, description = "A URL is invalid", body = str)
+        )
+    )]
+    async fn patch_link(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        Json(body): Json<PatchLinkBody>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("link", id);
+
+        if let Some(message) = invalid_url(
+            body.url
+                .as_deref()
+                .into_iter()
+                .chain(body.fallback_url.as_deref().filter(|url| !url.is_empty())),
+        ) {
+            return Ok((StatusCode::BAD_REQUEST, message).into_response());
+        }
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $link WHERE fn::can_access(id, $user)")
+            .bind(("link", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Link not found").into_response());
+        }
+
+        let owner_group =
+            match shortcut::requested_owner_group(&db, &userid, body.owner_group.as_deref()).await?
+            {
+                Ok(owner_group) => owner_group,
+                Err(response) => return Ok(response),
+            };
+
+        db.query(
+            "
+                BEGIN;
+                LET $previous = SELECT VALUE url FROM ONLY $link;
+                IF $url != NONE AND $url != $previous {
+                    UPDATE $link SET url = $url;
+                    RELATE $user->changed->$link CONTENT { previous_url: $previous };
+                };
+                IF $fallback_url != NONE {
+                    UPDATE $link SET fallback_url = IF $fallback_url != '' THEN $fallback_url END;
+                };
+                IF $title != NONE {
+                    UPDATE $link SET title = IF $title != '' THEN $title END;
+                };
+                IF $description != NONE {
+                    UPDATE $link SET description = IF $description != '' THEN $description END;
+                };
+                IF $notes != NONE {
+                    UPDATE $link SET notes = IF $notes != '' THEN $notes END;
+                };
+                IF $tags != NONE {
+                    UPDATE $link SET tags = $tags;
+                };
+                COMMIT;
+            ",
+        )
+        .bind(("link", id.clone()))
+        .bind(("user", userid.deref().clone()))
+        .bind(("url", body.url))
+        .bind(("fallback_url", body.fallback_url))
+        .bind(("title", body.title))
+        .bind(("description", body.description))
+        .bind(("notes", body.notes))
+        .bind((
+            "tags",
+            body.tags
+                .map(|tags| normalize_tags(tags.iter().map(String::as_str))),
+        ))
+        .await?
+        .check()?;
+
+        if let Some(owner_group) = owner_group {
+            let shortcuts: Vec<RecordId> = db
+                .query("SELECT VALUE id FROM $link<-expands_to<-shortcut WHERE fn::can_access(id, $user)")
+                .bind(("link", id.clone()))
+                .bind(("user", userid.deref().clone()))
+                .await?
+                .take(0)?;
+
+            groups::set_owner(&db, &[vec![id.clone()], shortcuts].concat(), owner_group).await?;
+        }
+
+        Ok(Json(db.query(
+                "SELECT id, url, fallback_url, title, description, notes, tags, <-expands_to<-shortcut.shortlink AS shortcuts, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $link",
+            )
+            .bind(("link", id))
+            .await?
+            .take::<Option<GetLinkResponse>>(0)?
+            .ok_or_eyre("Failed to update link")?
+        ).into_response())
+    }
+
     /// Delete a link and all shortcuts pointing to it
+    ///
+    /// Shortcuts that have other destinations too are kept and just stop leading to this link.
+    /// Redirect rules leading to it are removed.
     #[utoipa::path(
         method(delete),
         path = PATH,
@@ -248,14 +576,21 @@ mod by_id {
         let deleted: Option<bool> = db.query(
             "
                 BEGIN;
-                IF array::len(SELECT id FROM $link WHERE array::any(array::matches(<-created<-user.id, $user))) == 0 {
+                IF array::len(SELECT id FROM $link WHERE fn::can_access(id, $user)) == 0 {
                     RETURN FALSE;
                     CANCEL;
                 } ELSE {
                     TRUE
                 };
+                LET $orphans = SELECT VALUE id FROM $link<-expands_to<-shortcut WHERE array::len(->expands_to) == 1;
+                fn::archive_changes(array::flatten([[$link], $orphans]));
                 DELETE ONLY $link<-created RETURN BEFORE;
-                DELETE (SELECT VALUE array::flatten([<-expands_to, <-expands_to<-shortcut, <-expands_to<-shortcut<-created]) FROM ONLY $link) RETURN BEFORE;
+                DELETE $link<-changed RETURN BEFORE;
+                DELETE $link<-owns RETURN BEFORE;
+                DELETE $link<-expands_to RETURN BEFORE;
+                DELETE array::flatten(SELECT VALUE array::flatten([<-created, <-changed, <-owns, ->logged, ->logged->visit]) FROM $orphans) RETURN BEFORE;
+                DELETE $orphans RETURN BEFORE;
+                UPDATE shortcut SET rules = rules[WHERE link != $link] WHERE rules.link CONTAINS $link;
                 DELETE ONLY $link RETURN BEFORE;
                 COMMIT;
             ",
@@ -271,4 +606,51 @@ mod by_id {
             ("Link deleted successfully").into_response()
         })
     }
+
+    mod history {
+        use super::*;
+
+        const PATH: &str = "/api/link/{id}/history";
+
+        pub fn routes() -> Vec<Route> {
+            vec![(
+                RouteType::OpenApi(routes!(get_link_history)).scoped(RouteScope::Resource("link")),
+                true,
+            )]
+        }
+
+        /// Get the previous URLs of a link
+        ///
+        /// The history outlives the link: it's archived, not removed, when the link is deleted.
+        #[utoipa::path(
+            method(get),
+            path = PATH,
+            params(
+                ("id", description = "The id of the link")
+            ),
+            responses(
+                (status = OK, description = "Success", body = Vec<GetHistoryEntry>)
+            )
+        )]
+        async fn get_link_history(
+            State(db): State<SurrealDb>,
+            userid: SessionUserId,
+            Path(id): Path<String>,
+        ) -> AxumResult<impl IntoResponse> {
+            let id = RecordId::from_table_key("link", id);
+
+            if db
+                .query("SELECT VALUE id FROM ONLY $link WHERE fn::can_access(id, $user)")
+                .bind(("link", id.clone()))
+                .bind(("user", userid.deref().clone()))
+                .await?
+                .take::<Option<RecordId>>(0)?
+                .is_none()
+            {
+                return Ok((StatusCode::NOT_FOUND, "Link not found").into_response());
+            }
+
+            Ok(Json(shortcut::history(&db, &id).await?).into_response())
+        }
+    }
 }
@@ -0,0 +1,59 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::RouteScope, axum_error::AxumResult, quota, routes::RouteType, settings::ArcSettings,
+    state::SurrealDb, userid_extractor::SessionUserId,
+};
+
+use super::Route;
+
+pub fn routes() -> Vec<Route> {
+    vec![(
+        RouteType::OpenApi(routes!(get_usage)).scoped(RouteScope::Any),
+        true,
+    )]
+}
+
+/// Storage usage and limits, in bytes. Limits that are `null` don't apply.
+#[derive(Serialize, ToSchema)]
+struct GetUsageResponse {
+    /// Total size of your files
+    files: u64,
+
+    /// Total announced size of your unfinished resumable uploads
+    uploads: u64,
+
+    used: u64,
+    remaining: Option<u64>,
+
+    max_upload_size: Option<u64>,
+    max_total_size: Option<u64>,
+}
+
+/// Get how much storage you're using and what your quota is
+#[utoipa::path(
+    method(get),
+    path = "/api/me/usage",
+    responses(
+        (status = OK, description = "Success", body = GetUsageResponse)
+    )
+)]
+async fn get_usage(
+    State(db): State<SurrealDb>,
+    State(settings): State<ArcSettings>,
+    userid: SessionUserId,
+) -> AxumResult<Json<GetUsageResponse>> {
+    let usage = quota::usage(&db, &settings.quotas, &userid).await?;
+
+    Ok(Json(GetUsageResponse {
+        files: usage.files,
+        uploads: usage.uploads,
+        used: usage.used(),
+        remaining: usage.remaining(),
+        max_upload_size: usage.quota.max_upload_size,
+        max_total_size: usage.quota.max_total_size,
+    }))
+}
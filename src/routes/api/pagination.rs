@@ -0,0 +1,371 @@
+//! Cursor-based pagination of the list endpoints.
+//!
+//! Pages are ordered by a sort key with the record id as a tie-breaker, and the cursor is the
+//! (opaque, base64-encoded) sort key and id of the last item of the previous page, so pages stay
+//! consistent when records are created or deleted in between requests. The URL of the next page is
+//! sent in a `Link` header with `rel="next"`; the last page has none.
+
+use axum::{
+    http::{header, HeaderValue, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine as _};
+use color_eyre::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use surrealdb::Response as DbResponse;
+use utoipa::ToSchema;
+
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::Asc => "ASC",
+            Self::Desc => "DESC",
+        }
+    }
+
+    /// The comparison items after the cursor meet
+    fn comparison(self) -> &'static str {
+        match self {
+            Self::Asc => ">",
+            Self::Desc => "<",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CursorKey {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// The position after the last item of a page
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Cursor {
+    pub key: CursorKey,
+
+    /// The key of the record id of the item
+    pub id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> Result<String> {
+        Ok(BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()
+    }
+}
+
+/// Which page of a list was requested
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    pub limit: u32,
+    pub cursor: Option<Cursor>,
+    pub order: SortOrder,
+}
+
+impl Pagination {
+    /// Validates the pagination query parameters, returning a message for the client if they're invalid
+    pub fn new(
+        limit: Option<u32>,
+        cursor: Option<&str>,
+        order: SortOrder,
+    ) -> Result<Self, &'static str> {
+        let cursor = match cursor {
+            Some(cursor) => Some(Cursor::decode(cursor).ok_or("Invalid cursor")?),
+            None => None,
+        };
+
+        Ok(Self {
+            limit: limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            cursor,
+            order,
+        })
+    }
+
+    /// Builds a query selecting one page of `fields` of the records in `table` that meet all `conditions`,
+    /// ordered by the `sort_key` expression.
+    ///
+    /// `$cursor` needs to be bound to the cursor of this struct, and the result is read with [`take_page`],
+    /// given the limit of this struct.
+    pub fn query(&self, fields: &str, table: &str, conditions: &[&str], sort_key: &str) -> String {
+        self.query_from(fields, table, table, conditions, sort_key)
+    }
+
+    /// Like [`Pagination::query`], but selects from `source` instead of the whole table, e.g. a
+    /// parameter holding the ids of some records of `table`.
+    ///
+    /// The statements setting such parameters can be sent before the query.
+    pub fn query_from(
+        &self,
+        fields: &str,
+        table: &str,
+        source: &str,
+        conditions: &[&str],
+        sort_key: &str,
+    ) -> String {
+        let mut conditions = conditions.to_vec();
+        let after_cursor = format!(
+            "({sort_key} {cmp} $cursor.key OR ({sort_key} = $cursor.key AND id {cmp} type::thing('{table}', $cursor.id)))",
+            cmp = self.order.comparison(),
+        );
+        if self.cursor.is_some() {
+            conditions.push(&after_cursor);
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        format!(
+            "
+                LET $page = (SELECT {fields}, {sort_key} AS sort_key FROM {source} {where_clause} ORDER BY sort_key {order}, id {order} LIMIT {fetch});
+                RETURN $page;
+                RETURN (SELECT sort_key AS key, <string> record::id(id) AS id FROM $page);
+            ",
+            order = self.order.keyword(),
+            // one more item than requested is fetched to know whether there's a next page
+            fetch = self.limit + 1,
+        )
+    }
+}
+
+/// Reads a page of at most `limit` items from the response to a query built by [`Pagination::query`],
+/// which ends the response
+pub fn take_page<T: DeserializeOwned>(
+    mut response: DbResponse,
+    limit: u32,
+) -> Result<(Vec<T>, Option<Cursor>)> {
+    let last = response.num_statements() - 1;
+    Ok(split_page(
+        response.take(last - 1)?,
+        response.take(last)?,
+        limit,
+    ))
+}
+
+/// Cuts the fetched items down to a page of at most `limit` items, returning the cursor after the
+/// last one if more items were fetched than fit on the page.
+fn split_page<T>(mut items: Vec<T>, cursors: Vec<Cursor>, limit: u32) -> (Vec<T>, Option<Cursor>) {
+    let limit = limit as usize;
+    if items.len() <= limit {
+        return (items, None);
+    }
+
+    items.truncate(limit);
+    (items, cursors.into_iter().nth(limit - 1))
+}
+
+/// Responds with the items of a page, linking the next page if there is one
+pub fn page_response<T: Serialize>(
+    uri: &Uri,
+    items: Vec<T>,
+    next: Option<Cursor>,
+) -> Result<Response> {
+    let mut response = Json(items).into_response();
+
+    if let Some(next) = next {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(
+                url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+                    .filter(|(name, _)| name != "cursor"),
+            )
+            .append_pair("cursor", &next.encode()?)
+            .finish();
+
+        response.headers_mut().insert(
+            header::LINK,
+            HeaderValue::try_from(format!("<{}?{query}>; rel=\"next\"", uri.path()))?,
+        );
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_survive_encoding() {
+        for key in [
+            CursorKey::Int(-42),
+            CursorKey::Float(1.5),
+            CursorKey::Text("https://example.com/?a=b".to_string()),
+        ] {
+            let cursor = Cursor {
+                key,
+                id: "abc123".to_string(),
+            };
+
+            let encoded = cursor.encode().unwrap();
+            assert!(encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+            let decoded = Cursor::decode(&encoded).unwrap();
+            assert_eq!(decoded.id, cursor.id);
+            assert_eq!(
+                serde_json::to_value(&decoded.key).unwrap(),
+                serde_json::to_value(&cursor.key).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn integer_keys_stay_integers() {
+        let cursor = Cursor {
+            key: CursorKey::Int(7),
+            id: "x".to_string(),
+        };
+
+        let decoded = Cursor::decode(&cursor.encode().unwrap()).unwrap();
+        assert!(matches!(decoded.key, CursorKey::Int(7)));
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        assert!(Cursor::decode("not base64!").is_none());
+        assert!(Cursor::decode(&BASE64_URL_SAFE_NO_PAD.encode("{}")).is_none());
+        assert_eq!(
+            Pagination::new(None, Some("garbage"), SortOrder::Asc).unwrap_err(),
+            "Invalid cursor"
+        );
+    }
+
+    #[test]
+    fn clamps_the_limit() {
+        let limit = |limit| Pagination::new(limit, None, SortOrder::Desc).unwrap().limit;
+
+        assert_eq!(limit(None), DEFAULT_LIMIT);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(10)), 10);
+        assert_eq!(limit(Some(u32::MAX)), MAX_LIMIT);
+    }
+
+    fn cursor(id: &str) -> Cursor {
+        Cursor {
+            key: CursorKey::Text(format!("key of {id}")),
+            id: id.to_string(),
+        }
+    }
+
+    /// Splits `fetched` items into a page like [`take_page`] does, with cursors matching the items
+    fn page(fetched: &[&str], limit: u32) -> (Vec<String>, Option<Cursor>) {
+        split_page(
+            fetched.iter().map(|id| id.to_string()).collect(),
+            fetched.iter().map(|id| cursor(id)).collect(),
+            limit,
+        )
+    }
+
+    fn next_link(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(header::LINK)
+            .map(|link| link.to_str().unwrap())
+    }
+
+    #[test]
+    fn a_full_page_without_more_items_is_the_last() {
+        let (items, next) = page(&["a", "b", "c"], 3);
+
+        assert_eq!(items, ["a", "b", "c"]);
+        assert!(next.is_none());
+    }
+
+    #[test]
+    fn an_extra_item_means_there_is_a_next_page() {
+        let (items, next) = page(&["a", "b", "c", "d"], 3);
+
+        assert_eq!(items, ["a", "b", "c"]);
+
+        // the next page starts after the last item of this one, not at the extra item
+        let next = next.unwrap();
+        assert_eq!(next.id, "c");
+        assert!(matches!(next.key, CursorKey::Text(key) if key == "key of c"));
+    }
+
+    #[test]
+    fn short_and_empty_pages_are_the_last() {
+        let (items, next) = page(&["a"], 3);
+        assert_eq!(items, ["a"]);
+        assert!(next.is_none());
+
+        let (items, next) = page(&[], 3);
+        assert!(items.is_empty());
+        assert!(next.is_none());
+    }
+
+    #[test]
+    fn the_last_page_has_no_next_link() {
+        let uri: Uri = "/api/link?limit=3".parse().unwrap();
+        let (items, next) = page(&["a", "b"], 3);
+
+        let response = page_response(&uri, items, next).unwrap();
+
+        assert!(next_link(&response).is_none());
+    }
+
+    #[test]
+    fn links_the_next_page_with_a_cursor_after_the_last_item() {
+        let uri: Uri = "/api/link?limit=2&q=rust+docs&cursor=old".parse().unwrap();
+        let (items, next) = page(&["a", "b", "c"], 2);
+
+        let response = page_response(&uri, items, next).unwrap();
+        let link = next_link(&response).unwrap();
+
+        let url = link
+            .strip_prefix('<')
+            .and_then(|link| link.strip_suffix(">; rel=\"next\""))
+            .unwrap();
+        let (path, query) = url.split_once('?').unwrap();
+        assert_eq!(path, "/api/link");
+
+        let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(params[0], ("limit".to_string(), "2".to_string()));
+        assert_eq!(params[1], ("q".to_string(), "rust docs".to_string()));
+        assert_eq!(params.len(), 3);
+
+        // the cursor of the previous page is replaced, and the new one decodes to the last item
+        let (name, cursor) = &params[2];
+        assert_eq!(name, "cursor");
+        let pagination = Pagination::new(Some(2), Some(cursor), SortOrder::Asc).unwrap();
+        assert_eq!(pagination.cursor.unwrap().id, "b");
+    }
+
+    #[test]
+    fn selects_from_the_source_but_continues_in_the_table() {
+        let cursor = Cursor {
+            key: CursorKey::Int(3),
+            id: "abc".to_string(),
+        }
+        .encode()
+        .unwrap();
+
+        let query = Pagination::new(Some(5), Some(&cursor), SortOrder::Asc)
+            .unwrap()
+            .query_from("id", "link", "$accessible", &[], "visits");
+
+        assert!(query.contains("FROM $accessible WHERE"));
+        assert!(query.contains("type::thing('link', $cursor.id)"));
+    }
+}
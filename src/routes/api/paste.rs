@@ -0,0 +1,241 @@
+use std::ops::Deref;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use color_eyre::eyre::{eyre, OptionExt};
+use serde::{Deserialize, Serialize};
+use surrealdb::RecordId;
+use utoipa::ToSchema;
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::RouteScope,
+    axum_error::AxumResult,
+    routes::RouteType,
+    schema::{Created, PartialCreated, PartialPaste, Paste},
+    serialize_recordid::serialize_recordid_as_key,
+    state::SurrealDb,
+    userid_extractor::SessionUserId,
+};
+
+use super::{
+    shortcut::{self, PostShortcutOptions},
+    Route,
+};
+
+const PATH: &str = "/api/paste";
+
+pub fn routes() -> Vec<Route> {
+    [
+        vec![(
+            RouteType::OpenApi(routes!(get_paste_list, post_paste_list))
+                .scoped(RouteScope::Resource("paste")),
+            true,
+        )],
+        by_id::routes(),
+    ]
+    .concat()
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct GetPasteResponse {
+    #[schema(value_type = String)]
+    #[serde(serialize_with = "serialize_recordid_as_key")]
+    pub id: RecordId,
+    pub shortcuts: Vec<String>,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    pub content: String,
+}
+
+/// Get all pastes you have access to
+#[utoipa::path(
+    method(get),
+    path = PATH,
+    responses(
+        (status = OK, description = "Success", body = Vec<GetPasteResponse>)
+    )
+)]
+async fn get_paste_list(
+    State(db): State<SurrealDb>,
+    userid: SessionUserId,
+) -> AxumResult<Json<Vec<GetPasteResponse>>> {
+    Ok(Json(
+        db.query(
+            "SELECT VALUE ->created->paste.{id, title, language, content, shortcuts: <-expands_to<-shortcut.shortlink} FROM ONLY $user",
+        )
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take(0)?,
+    ))
+}
+
+/// Create a new paste
+#[utoipa::path(
+    method(post),
+    path = PATH,
+    request_body = PostPasteBody,
+    responses(
+        (status = OK, description = "Success", body = GetPasteResponse)
+    )
+)]
+async fn post_paste_list(
+    State(db): State<SurrealDb>,
+    userid: SessionUserId,
+    Json(body): Json<PostPasteBody>,
+) -> AxumResult<impl IntoResponse> {
+    let shortcuts = body
+        .shortcuts
+        .unwrap_or_else(|| vec![shortcut::random_shortlink()]);
+
+    let collisions = shortcut::find_collisions(&db, &shortcuts).await?;
+
+    if !collisions.is_empty() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("Shortcuts already exist: {}", collisions.join(", ")),
+        )
+            .into_response());
+    }
+
+    let options = body.options.into_options().await?;
+
+    let created_paste: Paste = db
+        .create("paste")
+        .content(PartialPaste {
+            title: body.title,
+            language: body.language,
+            content: body.content,
+        })
+        .await?
+        .ok_or_eyre("Failed to create paste")?;
+
+    let paste_created_rel: Vec<Created> = db
+        .insert("created")
+        .relation(PartialCreated {
+            object: created_paste.id.clone(),
+            user: userid.deref().clone(),
+        })
+        .await?;
+
+    if paste_created_rel.is_empty() {
+        let _: Option<Paste> = db.delete(&created_paste.id).await?;
+        return Err(eyre!("Failed to create paste").into());
+    }
+
+    shortcut::create_shortcuts(&db, &userid, &created_paste.id, &shortcuts, &options).await?;
+
+    Ok(Json(db.query(
+            "SELECT id, title, language, content, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $paste WHERE array::any(array::matches(<-created<-user.id, $user))",
+        )
+        .bind(("paste", created_paste.id))
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take::<Option<GetPasteResponse>>(0)?.ok_or_eyre("Failed to create paste")?
+        ).into_response())
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct PostPasteBody {
+    /// The short URLs to create for this paste. Set to `null` to get 1 random 10-character shortcut.
+    shortcuts: Option<Vec<String>>,
+    title: Option<String>,
+
+    /// The language used for syntax highlighting, as a name or file extension (e.g. `rust` or `rs`). Guessed from the first line if not set.
+    language: Option<String>,
+    content: String,
+
+    #[serde(flatten)]
+    options: PostShortcutOptions,
+}
+
+mod by_id {
+    use axum::{extract::Path, http::StatusCode, response::IntoResponse};
+
+    use super::*;
+
+    const PATH: &str = "/api/paste/{id}";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(get_paste, delete_paste))
+                .scoped(RouteScope::Resource("paste")),
+            true,
+        )]
+    }
+
+    /// Get a specific paste by id
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        params(
+            ("id", description = "The id of the paste to get")
+        ),
+        responses(
+            (status = OK, description = "Success", body = GetPasteResponse)
+        )
+    )]
+    async fn get_paste(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("paste", id);
+
+        match db.query(
+            "SELECT id, title, language, content, <-expands_to<-shortcut.shortlink AS shortcuts FROM ONLY $paste WHERE array::any(array::matches(<-created<-user.id, $user))",
+        )
+        .bind(("paste", id))
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take::<Option<GetPasteResponse>>(0)? {
+            Some(paste) => Ok(Json(paste).into_response()),
+            None => Ok((StatusCode::NOT_FOUND, "Paste not found").into_response()),
+        }
+    }
+
+    /// Delete a paste and all shortcuts pointing to it
+    #[utoipa::path(
+        method(delete),
+        path = PATH,
+        params(
+            ("id", description = "The id of the paste to delete")
+        ),
+        responses(
+            (status = OK, description = "Success", body = str)
+        )
+    )]
+    async fn delete_paste(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("paste", id);
+
+        let deleted: Option<bool> = db.query(
+            "
+                BEGIN;
+                IF array::len(SELECT id FROM $paste WHERE array::any(array::matches(<-created<-user.id, $user))) == 0 {
+                    RETURN FALSE;
+                    CANCEL;
+                } ELSE {
+                    TRUE
+                };
+                fn::archive_changes($paste<-expands_to<-shortcut);
+                DELETE ONLY $paste<-created RETURN BEFORE;
+                DELETE (SELECT VALUE array::flatten([<-expands_to, <-expands_to<-shortcut, <-expands_to<-shortcut<-created, <-expands_to<-shortcut<-changed, <-expands_to<-shortcut<-owns, <-expands_to<-shortcut->logged, <-expands_to<-shortcut->logged->visit]) FROM ONLY $paste) RETURN BEFORE;
+                DELETE ONLY $paste RETURN BEFORE;
+                COMMIT;
+            ",
+        )
+        .bind(("paste", id))
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take(0)?;
+
+        Ok(if matches!(deleted, Some(false) | None) {
+            (StatusCode::NOT_FOUND, "Paste not found").into_response()
+        } else {
+            ("Paste deleted successfully").into_response()
+        })
+    }
+}
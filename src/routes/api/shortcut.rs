@@ -1,64 +1,490 @@
 use std::ops::Deref;
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use color_eyre::eyre::{eyre, ContextCompat, OptionExt};
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::{
+    eyre::{eyre, ContextCompat, OptionExt},
+    Result,
+};
 use rand::distr::{Alphanumeric, SampleString as _};
 use serde::{Deserialize, Serialize};
-use surrealdb::RecordId;
-use utoipa::ToSchema;
+use surrealdb::{Datetime, RecordId};
+use utoipa::{IntoParams, ToSchema};
 use utoipa_axum::routes;
 
 use crate::{
+    api_token::RouteScope,
     axum_error::AxumResult,
+    groups, password,
     routes::RouteType,
-    schema::{Created, ExpandsTo, PartialCreated, PartialExpandsTo, PartialShortcut, Shortcut},
-    serialize_recordid::{deserialize_recordid_from_key_for_link, serialize_recordid_as_key},
+    schema::{
+        default_weight, Created, ExpandsTo, PartialCreated, PartialExpandsTo, PartialShortcut,
+        RedirectRule, Rotation, RuleConditions, Shortcut, ShortcutOptions,
+    },
+    serialize_recordid::{
+        deserialize_recordid_from_key_for_link, serialize_recordid_as_key,
+        serialize_recordid_vec_as_key,
+    },
+    settings::RedirectStatus,
     state::SurrealDb,
     userid_extractor::SessionUserId,
 };
 
-use super::Route;
+use super::{
+    file::parse_flag,
+    pagination::{page_response, take_page, Pagination, SortOrder},
+    Route,
+};
 
 const PATH: &str = "/api/shortcut";
 
 pub fn routes() -> Vec<Route> {
     [
         vec![(
-            RouteType::OpenApi(routes!(get_shortcut_list, post_shortcut_list)),
+            RouteType::OpenApi(routes!(get_shortcut_list, post_shortcut_list))
+                .scoped(RouteScope::Resource("shortcut")),
             true,
         )],
         by_id::routes(),
+        destinations::routes(),
+        rules::routes(),
+        stats::routes(),
     ]
     .concat()
 }
 
+/// Generates a random 10-character shortlink
+pub fn random_shortlink() -> String {
+    let mut rng = rand::rng();
+    Alphanumeric.sample_string(&mut rng, 10)
+}
+
+/// Returns the shortlinks from `shortlinks` that are already in use
+pub async fn find_collisions(db: &SurrealDb, shortlinks: &[String]) -> Result<Vec<String>> {
+    Ok(db
+        .query("SELECT VALUE shortlink FROM shortcut WHERE array::any(array::matches($shortcuts, shortlink))")
+        .bind(("shortcuts", shortlinks.to_vec()))
+        .await?
+        .take(0)?)
+}
+
+/// Turns a shortlink into the slug it's stored as, the same way the database does
+async fn slug(db: &SurrealDb, shortlink: &str) -> Result<String> {
+    db.query("RETURN string::slug($shortlink)")
+        .bind(("shortlink", shortlink.to_string()))
+        .await?
+        .take::<Option<String>>(0)?
+        .ok_or_eyre("Failed to slugify shortlink")
+}
+
+/// Creates shortcuts owned by `user` that expand to `object`
+pub async fn create_shortcuts(
+    db: &SurrealDb,
+    user: &RecordId,
+    object: &RecordId,
+    shortlinks: &[String],
+    options: &ShortcutOptions,
+) -> Result<Vec<Shortcut>> {
+    let created_shortcuts: Vec<Shortcut> = db
+        .insert("shortcut")
+        .content(
+            shortlinks
+                .iter()
+                .map(|shortlink| PartialShortcut {
+                    shortlink: shortlink.clone(),
+                    password: options.password.clone(),
+                    max_visits: options.max_visits,
+                    visits: 0,
+                    burn_after_read: options.burn_after_read,
+                    not_before: options.not_before.clone(),
+                    expires_at: options.expires_at.clone(),
+                    redirect_status: options.redirect_status,
+                    passthrough: options.passthrough,
+                    rotation: Rotation::default(),
+                    rules: Vec::new(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    if created_shortcuts.len() != shortlinks.len() {
+        return Err(eyre!("Failed to create shortcuts"));
+    }
+
+    let shortcuts_created_rel: Vec<Created> = db
+        .insert("created")
+        .relation(
+            created_shortcuts
+                .iter()
+                .map(|shortcut| PartialCreated {
+                    object: shortcut.id.clone(),
+                    user: user.clone(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    if shortcuts_created_rel.len() != created_shortcuts.len() {
+        return Err(eyre!("Failed to create shortcuts"));
+    }
+
+    let expands_to_rel: Vec<ExpandsTo> = db
+        .insert("expands_to")
+        .relation(
+            created_shortcuts
+                .iter()
+                .map(|shortcut| PartialExpandsTo {
+                    object: object.clone(),
+                    shortcut: shortcut.id.clone(),
+                    weight: default_weight(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    if expands_to_rel.len() != created_shortcuts.len() {
+        return Err(eyre!("Failed to create shortcuts"));
+    }
+
+    Ok(created_shortcuts)
+}
+
+/// Deletes an object along with all shortcuts pointing to it, regardless of who owns it
+pub async fn delete_object(db: &SurrealDb, object: &RecordId) -> Result<()> {
+    db.query(
+        "
+            BEGIN;
+            fn::archive_changes(array::flatten([[$object], $object<-expands_to<-shortcut]));
+            DELETE $object<-created;
+            DELETE $object<-changed;
+            DELETE $object<-owns;
+            DELETE (SELECT VALUE array::flatten([<-expands_to, <-expands_to<-shortcut, <-expands_to<-shortcut<-created, <-expands_to<-shortcut<-changed, <-expands_to<-shortcut<-owns, <-expands_to<-shortcut->logged, <-expands_to<-shortcut->logged->visit]) FROM ONLY $object);
+            DELETE $object;
+            UPDATE shortcut SET rules = rules[WHERE link != $object] WHERE rules.link CONTAINS $object;
+            COMMIT;
+        ",
+    )
+    .bind(("object", object.clone()))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Looks up the group a user asked to make the owner of an object.
+///
+/// The outer `Option` is `None` if the owner shouldn't change, and an empty name makes the object
+/// personal again. Returns an error response if the user isn't a member of the group.
+pub async fn requested_owner_group(
+    db: &SurrealDb,
+    user: &RecordId,
+    name: Option<&str>,
+) -> AxumResult<Result<Option<Option<RecordId>>, Response>> {
+    Ok(match name {
+        None => Ok(None),
+        Some("") => Ok(Some(None)),
+        Some(name) => match groups::membership(db, user, name).await? {
+            Some(group) => Ok(Some(Some(group))),
+            None => Err((
+                StatusCode::BAD_REQUEST,
+                format!("You aren't a member of the group {name}"),
+            )
+                .into_response()),
+        },
+    })
+}
+
+/// A change of a link or shortcut, shared by the history endpoints of both
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct GetHistoryEntry {
+    #[schema(value_type = String)]
+    pub timestamp: Datetime,
+
+    /// The name of the user who made the change
+    pub changed_by: Option<String>,
+
+    /// The URL of the link before the change
+    pub previous_url: Option<String>,
+
+    /// The short link of the shortcut before the change
+    pub previous_shortlink: Option<String>,
+
+    /// The ids of the objects the shortcut expanded to before the change, if they changed
+    #[schema(value_type = Vec<String>)]
+    #[serde(default, serialize_with = "serialize_recordid_vec_as_key")]
+    pub previous_objects: Vec<RecordId>,
+}
+
+/// Returns the changes of a link or shortcut, newest first
+///
+/// The changes are never removed: deleting a link or shortcut moves them into `archived_change`,
+/// where they're kept under the id of the deleted record, see `fn::archive_changes`.
+pub async fn history(db: &SurrealDb, object: &RecordId) -> Result<Vec<GetHistoryEntry>> {
+    Ok(db
+        .query("SELECT timestamp, in.name AS changed_by, previous_url, previous_shortlink, previous_objects FROM $object<-changed ORDER BY timestamp DESC")
+        .bind(("object", object.clone()))
+        .await?
+        .take(0)?)
+}
+
+/// Settings for newly created shortcuts, shared by all endpoints that create them
+#[derive(Deserialize, Serialize, ToSchema, Default)]
+pub struct PostShortcutOptions {
+    /// A password that has to be entered before following the shortcut.
+    pub password: Option<String>,
+
+    /// After how many visits the shortcut stops working.
+    pub max_visits: Option<u64>,
+
+    /// Delete the object the shortcut points to after it has been visited once.
+    #[serde(default)]
+    pub burn_after_read: bool,
+
+    /// When the shortcut starts working, as an RFC 3339 timestamp.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub not_before: Option<DateTime<Utc>>,
+
+    /// When the shortcut stops working, as an RFC 3339 timestamp.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// The status code links are redirected with: 301, 302, 307 or 308. Uses the instance default if not set.
+    #[schema(value_type = Option<u16>)]
+    pub redirect_status: Option<RedirectStatus>,
+
+    /// Append extra path segments to the destination link and merge query parameters into it,
+    /// so that `/{shortlink}/some/page?x=1` leads to `{link}/some/page?x=1`. Doesn't apply to go-link templates.
+    #[serde(default)]
+    pub passthrough: bool,
+}
+
+impl PostShortcutOptions {
+    /// Names of the form fields (and tus metadata keys) the options can be set with
+    pub const FIELDS: &[&str] = &[
+        "password",
+        "max_visits",
+        "burn_after_read",
+        "not_before",
+        "expires_at",
+        "redirect_status",
+        "passthrough",
+    ];
+
+    /// Sets an option from a form field or tus metadata value, returning an error message if the value is invalid
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid {name}");
+
+        match name {
+            "password" => self.password = Some(value.to_string()),
+            "max_visits" => self.max_visits = Some(value.trim().parse().map_err(|_| invalid())?),
+            "burn_after_read" => self.burn_after_read = parse_flag(value),
+            "passthrough" => self.passthrough = parse_flag(value),
+            "not_before" => {
+                self.not_before = Some(value.trim().parse().map_err(|_| invalid())?);
+            }
+            "expires_at" => {
+                self.expires_at = Some(value.trim().parse().map_err(|_| invalid())?);
+            }
+            "redirect_status" => {
+                let status: u16 = value.trim().parse().map_err(|_| invalid())?;
+                self.redirect_status = Some(status.try_into()?);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub async fn into_options(self) -> Result<ShortcutOptions> {
+        // Argon2 is deliberately slow, so keep it off the async workers
+        let password = match self.password {
+            Some(password) => Some(
+                tokio::task::spawn_blocking(move || password::hash_password(&password)).await??,
+            ),
+            None => None,
+        };
+
+        Ok(ShortcutOptions {
+            password,
+            max_visits: self.max_visits,
+            burn_after_read: self.burn_after_read,
+            not_before: self.not_before.map(Datetime::from),
+            expires_at: self.expires_at.map(Datetime::from),
+            redirect_status: self.redirect_status,
+            passthrough: self.passthrough,
+        })
+    }
+}
+
 #[derive(Deserialize, Serialize, ToSchema)]
 struct GetShortcutResponse {
     #[schema(value_type = String)]
     #[serde(serialize_with = "serialize_recordid_as_key")]
     id: RecordId,
     shortlink: String,
-    // TODO: add the expanded link
+    password_protected: bool,
+    max_visits: Option<u64>,
+    visits: u64,
+    burn_after_read: bool,
+
+    #[schema(value_type = Option<String>)]
+    not_before: Option<Datetime>,
+
+    #[schema(value_type = Option<String>)]
+    expires_at: Option<Datetime>,
+
+    #[schema(value_type = Option<u16>)]
+    redirect_status: Option<RedirectStatus>,
+    passthrough: bool,
+
+    /// What the shortcut expands to
+    destinations: Vec<ShortcutDestination>,
+    rotation: Rotation,
+
+    /// Rules sending some visitors to other links, checked in order
+    #[serde(default)]
+    rules: Vec<ShortcutRule>,
+
+    /// The OIDC group whose members can manage the shortcut along with its creator
+    owner_group: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ShortcutRule {
+    /// The ID of the link visitors meeting the conditions are sent to
+    #[schema(value_type = String)]
+    #[serde(serialize_with = "serialize_recordid_as_key")]
+    link: RecordId,
+
+    #[serde(default)]
+    conditions: RuleConditions,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct ShortcutDestination {
+    /// The id of the link, file or paste
+    #[schema(value_type = String)]
+    #[serde(serialize_with = "serialize_recordid_as_key")]
+    object: RecordId,
+
+    #[serde(default = "default_weight")]
+    weight: u32,
 }
 
-/// Get all shortcuts you have access to
+/// What the shortcut list is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ShortcutSort {
+    /// When the shortcut was created
+    Created,
+    Shortlink,
+    Visits,
+}
+
+impl ShortcutSort {
+    /// SurrealQL expression giving the sort key of a shortcut
+    fn key(self) -> &'static str {
+        match self {
+            Self::Created => "time::nano((<-created.timestamp)[0])",
+            Self::Shortlink => "shortlink",
+            Self::Visits => "visits",
+        }
+    }
+
+    fn default_order(self) -> SortOrder {
+        match self {
+            Self::Shortlink => SortOrder::Asc,
+            Self::Created | Self::Visits => SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetShortcutListParams {
+    /// Only return shortcuts created after this RFC 3339 timestamp.
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_after: Option<DateTime<Utc>>,
+
+    /// Only return shortcuts created before this RFC 3339 timestamp.
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_before: Option<DateTime<Utc>>,
+
+    /// What to sort the shortcuts by. Defaults to `created`.
+    #[param(inline)]
+    sort: Option<ShortcutSort>,
+
+    /// The sort order. Defaults to ascending for `shortlink` and descending for everything else.
+    #[param(inline)]
+    order: Option<SortOrder>,
+
+    /// How many shortcuts to return, at most 1000. Defaults to 50.
+    limit: Option<u32>,
+
+    /// Where the page starts, taken from the `Link` header of the previous page.
+    cursor: Option<String>,
+}
+
+/// Get the shortcuts you have access to
+///
+/// The shortcuts are paginated. If there are more, the `Link` header contains the URL of the next page with `rel="next"`.
 #[utoipa::path(
     method(get),
     path = PATH,
+    params(GetShortcutListParams),
     responses(
-        (status = OK, description = "Success", body = Vec<GetShortcutResponse>)
+        (status = OK, description = "Success", body = Vec<GetShortcutResponse>),
+        (status = BAD_REQUEST, description = "The cursor is invalid", body = str)
     )
 )]
 async fn get_shortcut_list(
     State(db): State<SurrealDb>,
     userid: SessionUserId,
-) -> AxumResult<Json<Vec<GetShortcutResponse>>> {
-    Ok(Json(
-        db.query("SELECT VALUE ->created->shortcut.{id, shortlink} FROM ONLY $user")
-            .bind(("user", userid.deref().clone()))
-            .await?
-            .take(0)?,
-    ))
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<GetShortcutListParams>,
+) -> AxumResult<Response> {
+    let sort = params.sort.unwrap_or(ShortcutSort::Created);
+
+    let pagination = match Pagination::new(
+        params.limit,
+        params.cursor.as_deref(),
+        params.order.unwrap_or(sort.default_order()),
+    ) {
+        Ok(pagination) => pagination,
+        Err(message) => return Ok((StatusCode::BAD_REQUEST, message).into_response()),
+    };
+
+    let mut conditions = Vec::new();
+    if params.created_after.is_some() {
+        conditions.push("(<-created.timestamp)[0] > $created_after");
+    }
+    if params.created_before.is_some() {
+        conditions.push("(<-created.timestamp)[0] < $created_before");
+    }
+
+    let (shortcuts, next) = take_page::<GetShortcutResponse>(
+        db.query("LET $accessible = array::union($user->created->shortcut, $user->member_of->oidc_group->owns->shortcut);")
+        .query(pagination.query_from(
+            "id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group",
+            "shortcut",
+            "$accessible",
+            &conditions,
+            sort.key(),
+        ))
+        .bind(("user", userid.deref().clone()))
+        .bind(("created_after", params.created_after.map(Datetime::from)))
+        .bind(("created_before", params.created_before.map(Datetime::from)))
+        .bind(("cursor", pagination.cursor))
+        .await?,
+        pagination.limit,
+    )?;
+
+    Ok(page_response(&uri, shortcuts, next)?)
 }
 
 /// Create a new shortcut
@@ -75,10 +501,7 @@ async fn post_shortcut_list(
     userid: SessionUserId,
     Json(body): Json<PostShortcutBody>,
 ) -> AxumResult<impl IntoResponse> {
-    let shortlink = body.shorturl.unwrap_or_else(|| {
-        let mut rng = rand::rng();
-        Alphanumeric.sample_string(&mut rng, 10)
-    });
+    let shortlink = body.shorturl.unwrap_or_else(random_shortlink);
 
     let collision: Vec<String> = db
         .query("SELECT VALUE shortlink FROM shortcut WHERE shortlink = $shortlink")
@@ -95,18 +518,36 @@ async fn post_shortcut_list(
     }
 
     let link_id = db
-        .query(
-            "SELECT VALUE id FROM link WHERE array::any(array::matches(<-created<-user.id, $user))",
-        )
+        .query("SELECT VALUE id FROM ONLY $link WHERE fn::can_access(id, $user)")
         .bind(("link", body.link))
         .bind(("user", userid.deref().clone()))
         .await?
         .take::<Option<RecordId>>(0)?
         .ok_or_eyre("Link not found")?;
 
+    let owner_group = match requested_owner_group(&db, &userid, body.owner_group.as_deref()).await?
+    {
+        Ok(owner_group) => owner_group.flatten(),
+        Err(response) => return Ok(response),
+    };
+
+    let options = body.options.into_options().await?;
+
     let created_shortcut: Shortcut = db
         .create("shortcut")
-        .content(PartialShortcut { shortlink })
+        .content(PartialShortcut {
+            shortlink,
+            password: options.password,
+            max_visits: options.max_visits,
+            visits: 0,
+            burn_after_read: options.burn_after_read,
+            not_before: options.not_before,
+            expires_at: options.expires_at,
+            redirect_status: options.redirect_status,
+            passthrough: options.passthrough,
+            rotation: Rotation::default(),
+            rules: Vec::new(),
+        })
         .await?
         .wrap_err("Failed to create shortcut")?;
 
@@ -127,6 +568,7 @@ async fn post_shortcut_list(
         .relation(PartialExpandsTo {
             object: link_id.clone(),
             shortcut: created_shortcut.id.clone(),
+            weight: default_weight(),
         })
         .await? as Vec<ExpandsTo>)
         .is_empty()
@@ -134,8 +576,12 @@ async fn post_shortcut_list(
         return Err(eyre!("Failed to create shortcut").into());
     }
 
+    if owner_group.is_some() {
+        groups::set_owner(&db, &[created_shortcut.id.clone()], owner_group).await?;
+    }
+
     Ok(Json(db.query(
-            "SELECT id, shortlink FROM ONLY $shortcut WHERE array::any(array::matches(<-created<-user.id, $user))",
+            "SELECT id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $shortcut WHERE fn::can_access(id, $user)",
         )
         .bind(("shortcut", created_shortcut.id))
         .bind(("user", userid.deref().clone()))
@@ -153,6 +599,12 @@ struct PostShortcutBody {
     #[schema(value_type = String)]
     #[serde(deserialize_with = "deserialize_recordid_from_key_for_link")]
     link: RecordId,
+
+    /// An OIDC group you're a member of, whose members can manage the shortcut too.
+    owner_group: Option<String>,
+
+    #[serde(flatten)]
+    options: PostShortcutOptions,
 }
 
 mod by_id {
@@ -163,10 +615,15 @@ mod by_id {
     const PATH: &str = "/api/shortcut/{id}";
 
     pub fn routes() -> Vec<Route> {
-        vec![(
-            RouteType::OpenApi(routes!(get_shortcut, delete_shortcut)),
-            true,
-        )]
+        [
+            vec![(
+                RouteType::OpenApi(routes!(get_shortcut, patch_shortcut, delete_shortcut))
+                    .scoped(RouteScope::Resource("shortcut")),
+                true,
+            )],
+            history::routes(),
+        ]
+        .concat()
     }
 
     /// Get a specific shortcut by id
@@ -188,7 +645,7 @@ mod by_id {
         let id = RecordId::from_table_key("shortcut", id);
 
         match db.query(
-            "SELECT id, shortlink FROM ONLY $shortcut WHERE array::any(array::matches(<-created<-user.id, $user))",
+            "SELECT id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $shortcut WHERE fn::can_access(id, $user)",
         )
         .bind(("shortcut", id))
         .bind(("user", userid.deref().clone()))
@@ -199,6 +656,164 @@ mod by_id {
         }
     }
 
+    #[derive(Deserialize, ToSchema)]
+    struct PatchShortcutBody {
+        /// The new short URL of the shortcut, stored as a slug like `foo-bar`. The old one stops working right away.
+        shortlink: Option<String>,
+
+        /// The ID of the link the shortcut should expand to instead. Only works for shortcuts to links.
+        #[schema(value_type = Option<String>)]
+        #[serde(default, deserialize_with = "deserialize_optional_link")]
+        link: Option<RecordId>,
+
+        /// An OIDC group you're a member of, whose members can manage the shortcut too.
+        /// Set to an empty string to make the shortcut personal again.
+        owner_group: Option<String>,
+    }
+
+    fn deserialize_optional_link<'de, D>(deserializer: D) -> Result<Option<RecordId>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Option::<String>::deserialize(deserializer)?
+            .map(|key| RecordId::from_table_key("link", key)))
+    }
+
+    /// Change the short URL of a shortcut or the link it expands to
+    ///
+    /// The previous values are kept in the history of the shortcut.
+    #[utoipa::path(
+        method(patch),
+        path = PATH,
+        params(
+            ("id", description = "The id of the shortcut to change")
+        ),
+        request_body = PatchShortcutBody,
+        responses(
+            (status = OK, description = "Success", body = GetShortcutResponse),
+            (status = BAD_REQUEST, description = "The short URL is invalid or taken, or the link wasn't found", body = str)
+        )
+    )]
+    async fn patch_shortcut(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        Json(body): Json<PatchShortcutBody>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("shortcut", id);
+
+        let Some(current) = db
+            .query("SELECT VALUE shortlink FROM ONLY $shortcut WHERE fn::can_access(id, $user)")
+            .bind(("shortcut", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<String>>(0)?
+        else {
+            return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+        };
+
+        let owner_group =
+            match requested_owner_group(&db, &userid, body.owner_group.as_deref()).await? {
+                Ok(owner_group) => owner_group,
+                Err(response) => return Ok(response),
+            };
+
+        // the shortlink is stored as a slug, so it's compared as one
+        let shortlink = match body.shortlink {
+            Some(shortlink) => Some(slug(&db, &shortlink).await?),
+            None => None,
+        }
+        .filter(|shortlink| *shortlink != current);
+
+        if let Some(shortlink) = &shortlink {
+            if shortlink.is_empty() {
+                return Ok((StatusCode::BAD_REQUEST, "Invalid shortlink").into_response());
+            }
+
+            if !find_collisions(&db, &[shortlink.clone()]).await?.is_empty() {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    format!("Shortcut already exists: {shortlink}"),
+                )
+                    .into_response());
+            }
+        }
+
+        if let Some(link) = &body.link {
+            let other_objects: Vec<RecordId> = db
+                .query(
+                    "SELECT VALUE out FROM $shortcut->expands_to WHERE record::tb(out) != 'link'",
+                )
+                .bind(("shortcut", id.clone()))
+                .await?
+                .take(0)?;
+
+            if !other_objects.is_empty() {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    "Only shortcuts to links can be pointed to another link",
+                )
+                    .into_response());
+            }
+
+            if db
+                .query("SELECT VALUE id FROM ONLY $link WHERE fn::can_access(id, $user)")
+                .bind(("link", link.clone()))
+                .bind(("user", userid.deref().clone()))
+                .await?
+                .take::<Option<RecordId>>(0)?
+                .is_none()
+            {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    format!("Link not found: {}", link.key()),
+                )
+                    .into_response());
+            }
+        }
+
+        db.query(
+            "
+                BEGIN;
+                LET $previous_objects = $shortcut->expands_to.out;
+                IF $shortlink != NONE {
+                    UPDATE $shortcut SET shortlink = $shortlink;
+                };
+                IF $link != NONE AND $previous_objects != [$link] {
+                    DELETE $shortcut->expands_to;
+                    RELATE $shortcut->expands_to->$link;
+                };
+                IF $shortlink != NONE OR ($link != NONE AND $previous_objects != [$link]) {
+                    RELATE $user->changed->$shortcut CONTENT {
+                        previous_shortlink: IF $shortlink != NONE THEN $previous_shortlink END,
+                        previous_objects: IF $link != NONE AND $previous_objects != [$link] THEN $previous_objects ELSE [] END,
+                    };
+                };
+                COMMIT;
+            ",
+        )
+        .bind(("shortcut", id.clone()))
+        .bind(("user", userid.deref().clone()))
+        .bind(("shortlink", shortlink))
+        .bind(("previous_shortlink", current))
+        .bind(("link", body.link))
+        .await?
+        .check()?;
+
+        if let Some(owner_group) = owner_group {
+            groups::set_owner(&db, &[id.clone()], owner_group).await?;
+        }
+
+        Ok(Json(db.query(
+                "SELECT id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $shortcut",
+            )
+            .bind(("shortcut", id))
+            .await?
+            .take::<Option<GetShortcutResponse>>(0)?
+            .ok_or_eyre("Failed to update shortcut")?
+        ).into_response())
+    }
+
     /// Delete a shortcut
     #[utoipa::path(
         method(delete),
@@ -220,14 +835,18 @@ mod by_id {
         let deleted: Option<bool> = db.query(
             "
                 BEGIN;
-                IF array::len(SELECT id FROM $shortcut WHERE array::any(array::matches(<-created<-user.id, $user))) == 0 {
+                IF array::len(SELECT id FROM $shortcut WHERE fn::can_access(id, $user)) == 0 {
                     RETURN FALSE;
                     CANCEL;
                 } ELSE {
                     TRUE
                 };
+                fn::archive_changes([$shortcut]);
                 DELETE ONLY $shortcut<-created RETURN BEFORE;
-                DELETE ONLY $shortcut->expands_to RETURN BEFORE;
+                DELETE $shortcut<-changed RETURN BEFORE;
+                DELETE $shortcut<-owns RETURN BEFORE;
+                DELETE $shortcut->expands_to RETURN BEFORE;
+                DELETE (SELECT VALUE array::flatten([->logged, ->logged->visit]) FROM ONLY $shortcut);
                 DELETE ONLY $shortcut RETURN BEFORE;
                 COMMIT;
             ",
@@ -243,4 +862,502 @@ mod by_id {
             ("ShortcuShortcut deleted successfully").into_response()
         })
     }
+
+    mod history {
+        use super::*;
+
+        const PATH: &str = "/api/shortcut/{id}/history";
+
+        pub fn routes() -> Vec<Route> {
+            vec![(
+                RouteType::OpenApi(routes!(get_shortcut_history))
+                    .scoped(RouteScope::Resource("shortcut")),
+                true,
+            )]
+        }
+
+        /// Get the previous short URLs and destinations of a shortcut
+        ///
+        /// The history outlives the shortcut: it's archived, not removed, when the shortcut is deleted.
+        #[utoipa::path(
+            method(get),
+            path = PATH,
+            params(
+                ("id", description = "The id of the shortcut")
+            ),
+            responses(
+                (status = OK, description = "Success", body = Vec<GetHistoryEntry>)
+            )
+        )]
+        async fn get_shortcut_history(
+            State(db): State<SurrealDb>,
+            userid: SessionUserId,
+            Path(id): Path<String>,
+        ) -> AxumResult<impl IntoResponse> {
+            let id = RecordId::from_table_key("shortcut", id);
+
+            if db
+                .query("SELECT VALUE id FROM ONLY $shortcut WHERE fn::can_access(id, $user)")
+                .bind(("shortcut", id.clone()))
+                .bind(("user", userid.deref().clone()))
+                .await?
+                .take::<Option<RecordId>>(0)?
+                .is_none()
+            {
+                return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+            }
+
+            Ok(Json(history(&db, &id).await?).into_response())
+        }
+    }
+}
+
+mod destinations {
+    use axum::extract::Path;
+
+    use super::*;
+
+    const PATH: &str = "/api/shortcut/{id}/destinations";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(put_shortcut_destinations))
+                .scoped(RouteScope::Resource("shortcut")),
+            true,
+        )]
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct PutDestination {
+        /// The ID of the link.
+        #[schema(value_type = String)]
+        #[serde(deserialize_with = "deserialize_recordid_from_key_for_link")]
+        link: RecordId,
+
+        /// How often the link is picked compared to the other ones. Defaults to 1.
+        #[serde(default = "default_weight")]
+        weight: u32,
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    struct PutDestinationsBody {
+        /// The links the shortcut expands to. Visitors are spread over them according to their weights.
+        destinations: Vec<PutDestination>,
+
+        /// How the link is picked for each visit.
+        #[serde(default)]
+        rotation: Rotation,
+    }
+
+    /// Replace the links a shortcut expands to
+    ///
+    /// Only works for shortcuts to links. Visits record which link they were sent to, see the statistics of the shortcut.
+    #[utoipa::path(
+        method(put),
+        path = PATH,
+        params(
+            ("id", description = "The id of the shortcut")
+        ),
+        request_body = PutDestinationsBody,
+        responses(
+            (status = OK, description = "Success", body = GetShortcutResponse),
+            (status = BAD_REQUEST, description = "The shortcut doesn't point to links, or a link wasn't found", body = str)
+        )
+    )]
+    async fn put_shortcut_destinations(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        Json(body): Json<PutDestinationsBody>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("shortcut", id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $shortcut WHERE fn::can_access(id, $user)")
+            .bind(("shortcut", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+        }
+
+        if body.destinations.is_empty() {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "At least one destination is needed",
+            )
+                .into_response());
+        }
+
+        let other_objects: Vec<RecordId> = db
+            .query("SELECT VALUE out FROM $shortcut->expands_to WHERE record::tb(out) != 'link'")
+            .bind(("shortcut", id.clone()))
+            .await?
+            .take(0)?;
+
+        if !other_objects.is_empty() {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "Only shortcuts to links can have several destinations",
+            )
+                .into_response());
+        }
+
+        let links: Vec<RecordId> = body
+            .destinations
+            .iter()
+            .map(|destination| destination.link.clone())
+            .collect();
+
+        let owned: Vec<RecordId> = db
+            .query("SELECT VALUE id FROM $links WHERE fn::can_access(id, $user)")
+            .bind(("links", links.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take(0)?;
+
+        if let Some(missing) = links.iter().find(|link| !owned.contains(link)) {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                format!("Link not found: {}", missing.key()),
+            )
+                .into_response());
+        }
+
+        db.query(
+            "
+                BEGIN;
+                LET $previous_objects = $shortcut->expands_to.out;
+                DELETE $shortcut->expands_to;
+                FOR $destination IN $destinations {
+                    LET $link = $destination.link;
+                    RELATE $shortcut->expands_to->$link SET weight = $destination.weight;
+                };
+                UPDATE $shortcut SET rotation = $rotation;
+                IF array::sort($previous_objects) != array::sort($shortcut->expands_to.out) {
+                    RELATE $user->changed->$shortcut CONTENT { previous_objects: $previous_objects };
+                };
+                COMMIT;
+            ",
+        )
+        .bind(("shortcut", id.clone()))
+        .bind(("user", userid.deref().clone()))
+        .bind(("destinations", body.destinations))
+        .bind(("rotation", body.rotation))
+        .await?
+        .check()?;
+
+        Ok(Json(db.query(
+                "SELECT id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $shortcut",
+            )
+            .bind(("shortcut", id))
+            .await?
+            .take::<Option<GetShortcutResponse>>(0)?
+            .ok_or_eyre("Failed to update shortcut")?
+        ).into_response())
+    }
+}
+
+mod rules {
+    use axum::extract::Path;
+
+    use super::*;
+
+    const PATH: &str = "/api/shortcut/{id}/rules";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(put_shortcut_rules))
+                .scoped(RouteScope::Resource("shortcut")),
+            true,
+        )]
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    struct PutRule {
+        /// The ID of the link visitors meeting the conditions are sent to.
+        #[schema(value_type = String)]
+        #[serde(deserialize_with = "deserialize_recordid_from_key_for_link")]
+        link: RecordId,
+
+        #[serde(default)]
+        conditions: RuleConditions,
+    }
+
+    #[derive(Deserialize, ToSchema)]
+    struct PutRulesBody {
+        /// The rules, in the order they're checked in. The first one whose conditions are met picks the link.
+        rules: Vec<PutRule>,
+    }
+
+    /// Checks the values of the conditions and normalizes them for matching
+    fn normalize(mut conditions: RuleConditions) -> Result<RuleConditions, String> {
+        if conditions.weekdays.iter().any(|day| !(1..=7).contains(day)) {
+            return Err("Weekdays have to be between 1 (Monday) and 7 (Sunday)".to_string());
+        }
+
+        if [conditions.from_hour, conditions.to_hour]
+            .iter()
+            .flatten()
+            .any(|hour| *hour > 23)
+        {
+            return Err("Hours have to be between 0 and 23".to_string());
+        }
+
+        if conditions.utc_offset.abs() >= 24 * 60 {
+            return Err("The UTC offset has to be less than a day".to_string());
+        }
+
+        for value in conditions
+            .languages
+            .iter_mut()
+            .chain(conditions.referrers.iter_mut())
+        {
+            *value = value.trim().to_lowercase();
+        }
+
+        Ok(conditions)
+    }
+
+    /// Replace the redirect rules of a shortcut
+    ///
+    /// Rules send visitors to another link depending on their platform, preferred language, referrer or the time of their visit.
+    /// They are checked in order, and visitors who don't meet the conditions of any rule go to the usual destinations of the shortcut.
+    /// Only works for shortcuts to links.
+    #[utoipa::path(
+        method(put),
+        path = PATH,
+        params(
+            ("id", description = "The id of the shortcut")
+        ),
+        request_body = PutRulesBody,
+        responses(
+            (status = OK, description = "Success", body = GetShortcutResponse),
+            (status = BAD_REQUEST, description = "The shortcut doesn't point to links, a link wasn't found or a condition is invalid", body = str)
+        )
+    )]
+    async fn put_shortcut_rules(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        Json(body): Json<PutRulesBody>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("shortcut", id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $shortcut WHERE fn::can_access(id, $user)")
+            .bind(("shortcut", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+        }
+
+        let other_objects: Vec<RecordId> = db
+            .query("SELECT VALUE out FROM $shortcut->expands_to WHERE record::tb(out) != 'link'")
+            .bind(("shortcut", id.clone()))
+            .await?
+            .take(0)?;
+
+        if !other_objects.is_empty() {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "Only shortcuts to links can have redirect rules",
+            )
+                .into_response());
+        }
+
+        let links: Vec<RecordId> = body.rules.iter().map(|rule| rule.link.clone()).collect();
+
+        let owned: Vec<RecordId> = db
+            .query("SELECT VALUE id FROM $links WHERE fn::can_access(id, $user)")
+            .bind(("links", links.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take(0)?;
+
+        if let Some(missing) = links.iter().find(|link| !owned.contains(link)) {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                format!("Link not found: {}", missing.key()),
+            )
+                .into_response());
+        }
+
+        let mut rules = Vec::with_capacity(body.rules.len());
+        for rule in body.rules {
+            match normalize(rule.conditions) {
+                Ok(conditions) => rules.push(RedirectRule {
+                    link: rule.link,
+                    conditions,
+                }),
+                Err(message) => return Ok((StatusCode::BAD_REQUEST, message).into_response()),
+            }
+        }
+
+        Ok(Json(db.query(
+                "
+                    UPDATE $shortcut SET rules = $rules;
+                    SELECT id, shortlink, password != NONE AS password_protected, max_visits, visits, burn_after_read, not_before, expires_at, redirect_status, passthrough, ->expands_to.{object: out, weight} AS destinations, rotation, rules, array::first(<-owns<-oidc_group.name) AS owner_group FROM ONLY $shortcut;
+                ",
+            )
+            .bind(("shortcut", id))
+            .bind(("rules", rules))
+            .await?
+            .take::<Option<GetShortcutResponse>>(1)?
+            .ok_or_eyre("Failed to update shortcut")?
+        ).into_response())
+    }
+}
+
+mod stats {
+    use axum::extract::Path;
+
+    use super::*;
+
+    const PATH: &str = "/api/shortcut/{id}/stats";
+
+    /// How many referrers are listed in the statistics
+    const TOP_REFERRERS: u32 = 10;
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(get_shortcut_stats))
+                .scoped(RouteScope::Resource("shortcut")),
+            true,
+        )]
+    }
+
+    #[derive(Deserialize, ToSchema, Clone, Copy, Default)]
+    #[serde(rename_all = "snake_case")]
+    enum StatsInterval {
+        Hour,
+        #[default]
+        Day,
+        /// Weeks starting on Monday
+        Week,
+    }
+
+    impl StatsInterval {
+        /// SurrealQL expression giving the start of the bucket a visit falls into
+        fn bucket(self) -> &'static str {
+            match self {
+                Self::Hour => "time::floor(timestamp, 1h)",
+                Self::Day => "time::floor(timestamp, 1d)",
+                // the Unix epoch was a Thursday, so weeks have to be shifted to start on Monday
+                Self::Week => "time::floor(timestamp - 4d, 1w) + 4d",
+            }
+        }
+    }
+
+    #[derive(Deserialize, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    struct GetStatsParams {
+        /// The size of the buckets of the time series. Buckets are aligned to UTC.
+        #[serde(default)]
+        #[param(inline)]
+        interval: StatsInterval,
+
+        /// Only count visits after this RFC 3339 timestamp.
+        #[param(value_type = Option<String>, format = DateTime)]
+        since: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct StatsBucket {
+        /// The start of the bucket
+        #[schema(value_type = String)]
+        start: Datetime,
+        visits: u64,
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct StatsReferrer {
+        /// The host of the page visitors came from
+        referrer: String,
+        visits: u64,
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct StatsVariant {
+        /// The id of the link, file or paste visitors were sent to
+        #[schema(value_type = String)]
+        #[serde(serialize_with = "serialize_recordid_as_key")]
+        variant: RecordId,
+        visits: u64,
+    }
+
+    #[derive(Deserialize, Serialize, ToSchema)]
+    struct GetStatsResponse {
+        total: u64,
+
+        /// Number of distinct anonymized IP addresses
+        unique_visitors: u64,
+
+        /// Visits per time bucket, oldest first. Buckets without visits are left out.
+        series: Vec<StatsBucket>,
+
+        /// The most common referrers, most common first
+        top_referrers: Vec<StatsReferrer>,
+
+        /// Visits per destination of the shortcut
+        variants: Vec<StatsVariant>,
+    }
+
+    /// Get the visit statistics of a shortcut
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        params(
+            ("id", description = "The id of the shortcut to get the statistics of"),
+            GetStatsParams
+        ),
+        responses(
+            (status = OK, description = "Success", body = GetStatsResponse)
+        )
+    )]
+    async fn get_shortcut_stats(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        Query(params): Query<GetStatsParams>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("shortcut", id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $shortcut WHERE fn::can_access(id, $user)")
+            .bind(("shortcut", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+        }
+
+        let stats: Option<GetStatsResponse> = db
+            .query(format!(
+                "
+                    LET $visits = (SELECT timestamp, referrer, ip, variant FROM $shortcut->logged->visit WHERE $since = NONE OR timestamp >= $since);
+                    RETURN {{
+                        total: array::len($visits),
+                        unique_visitors: array::len(array::distinct((SELECT VALUE ip FROM $visits WHERE ip != NONE))),
+                        series: (SELECT {bucket} AS start, count() AS visits FROM $visits GROUP BY start ORDER BY start),
+                        top_referrers: (SELECT referrer, count() AS visits FROM $visits WHERE referrer != NONE GROUP BY referrer ORDER BY visits DESC LIMIT {TOP_REFERRERS}),
+                        variants: (SELECT variant, count() AS visits FROM $visits WHERE variant != NONE GROUP BY variant ORDER BY visits DESC)
+                    }};
+                ",
+                bucket = params.interval.bucket(),
+            ))
+            .bind(("shortcut", id))
+            .bind(("since", params.since.map(Datetime::from)))
+            .await?
+            .take(1)?;
+
+        Ok(Json(stats.ok_or_eyre("Failed to get shortcut statistics")?).into_response())
+    }
 }
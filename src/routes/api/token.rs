@@ -0,0 +1,212 @@
+use std::ops::Deref;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Datetime, RecordId};
+use utoipa::ToSchema;
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::RouteScope,
+    api_token::{create_token, is_valid_scope, ALL_SCOPES},
+    axum_error::AxumResult,
+    routes::RouteType,
+    serialize_recordid::serialize_recordid_as_key,
+    state::SurrealDb,
+    userid_extractor::SessionUserId,
+};
+
+use super::Route;
+
+const PATH: &str = "/api/token";
+
+pub fn routes() -> Vec<Route> {
+    [
+        vec![(
+            RouteType::OpenApi(routes!(get_token_list, post_token_list))
+                .scoped(RouteScope::Resource("token")),
+            true,
+        )],
+        by_id::routes(),
+    ]
+    .concat()
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct GetTokenResponse {
+    #[schema(value_type = String)]
+    #[serde(serialize_with = "serialize_recordid_as_key")]
+    id: RecordId,
+    name: String,
+    scopes: Vec<String>,
+
+    #[schema(value_type = Option<String>)]
+    expires_at: Option<Datetime>,
+
+    #[schema(value_type = String)]
+    created_at: Datetime,
+}
+
+/// Get your API tokens
+///
+/// The tokens themselves are only shown once, when they're created.
+#[utoipa::path(
+    method(get),
+    path = PATH,
+    responses(
+        (status = OK, description = "Success", body = Vec<GetTokenResponse>)
+    )
+)]
+async fn get_token_list(
+    State(db): State<SurrealDb>,
+    userid: SessionUserId,
+) -> AxumResult<Json<Vec<GetTokenResponse>>> {
+    Ok(Json(
+        db.query(
+            "SELECT id, name, scopes ?? [$all] AS scopes, expires_at, (<-created.timestamp)[0] AS created_at FROM token WHERE array::any(array::matches(<-created<-user.id, $user)) ORDER BY created_at DESC",
+        )
+        .bind(("user", userid.deref().clone()))
+        .bind(("all", ALL_SCOPES))
+        .await?
+        .take(0)?,
+    ))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PostTokenBody {
+    /// What the token is used for, to recognize it later
+    name: String,
+
+    /// What the token can be used for: `*` for everything, or `read` and `write` scopes of
+    /// `link`, `shortcut`, `file`, `paste`, `token` and `admin`, like `link:read`.
+    scopes: Vec<String>,
+
+    /// When the token stops working, as an RFC 3339 timestamp. Set to `null` to keep it working until it's revoked.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PostTokenResponse {
+    #[serde(flatten)]
+    details: GetTokenResponse,
+
+    /// The token to send in the `Authorization: Bearer` header. It can't be retrieved again.
+    token: String,
+}
+
+/// Create a new API token
+///
+/// Scripts can use it to access the API with an `Authorization: Bearer <token>` header instead of logging in.
+#[utoipa::path(
+    method(post),
+    path = PATH,
+    request_body = PostTokenBody,
+    responses(
+        (status = OK, description = "Success", body = PostTokenResponse),
+        (status = BAD_REQUEST, description = "A scope is invalid, or the expiry is in the past", body = str)
+    )
+)]
+async fn post_token_list(
+    State(db): State<SurrealDb>,
+    userid: SessionUserId,
+    Json(body): Json<PostTokenBody>,
+) -> AxumResult<impl IntoResponse> {
+    if body.name.trim().is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "The name can't be empty").into_response());
+    }
+
+    if body.scopes.is_empty() {
+        return Ok((StatusCode::BAD_REQUEST, "At least one scope is needed").into_response());
+    }
+
+    if let Some(scope) = body.scopes.iter().find(|scope| !is_valid_scope(scope)) {
+        return Ok((StatusCode::BAD_REQUEST, format!("Invalid scope: {scope}")).into_response());
+    }
+
+    if body
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "The expiry has to be in the future",
+        )
+            .into_response());
+    }
+
+    let (created_token, token) = create_token(
+        &db,
+        &userid,
+        body.name.trim(),
+        body.scopes,
+        body.expires_at.map(Datetime::from),
+    )
+    .await?;
+
+    Ok(Json(PostTokenResponse {
+        details: GetTokenResponse {
+            id: created_token.id,
+            name: created_token.name,
+            scopes: created_token.scopes,
+            expires_at: created_token.expires_at,
+            created_at: Datetime::from(Utc::now()),
+        },
+        token,
+    })
+    .into_response())
+}
+
+mod by_id {
+    use axum::extract::Path;
+
+    use crate::api_token::revoke_token;
+
+    use super::*;
+
+    const PATH: &str = "/api/token/{id}";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::OpenApi(routes!(delete_token)).scoped(RouteScope::Resource("token")),
+            true,
+        )]
+    }
+
+    /// Revoke an API token
+    ///
+    /// The token stops working right away.
+    #[utoipa::path(
+        method(delete),
+        path = PATH,
+        params(
+            ("id", description = "The id of the token to revoke")
+        ),
+        responses(
+            (status = OK, description = "Success", body = str)
+        )
+    )]
+    async fn delete_token(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let id = RecordId::from_table_key("token", id);
+
+        if db
+            .query("SELECT VALUE id FROM ONLY $token WHERE array::any(array::matches(<-created<-user.id, $user))")
+            .bind(("token", id.clone()))
+            .bind(("user", userid.deref().clone()))
+            .await?
+            .take::<Option<RecordId>>(0)?
+            .is_none()
+        {
+            return Ok((StatusCode::NOT_FOUND, "Token not found").into_response());
+        }
+
+        revoke_token(&db, &id).await?;
+
+        Ok("Token revoked successfully".into_response())
+    }
+}
@@ -0,0 +1,615 @@
+//! Resumable uploads using the [tus](https://tus.io/protocols/resumable-upload) protocol.
+//!
+//! Supports the core protocol along with the `creation` and `termination` extensions. Partial
+//! uploads are kept in the staging directory and their offsets are tracked in SurrealDB, so they
+//! can be resumed after a server restart. Once an upload is complete, it's moved to the blob
+//! storage and turned into a regular file.
+//!
+//! Quotas are checked when an upload is created, against its announced length, which stays
+//! reserved until the upload is finished or deleted.
+
+use std::{collections::HashMap, io::SeekFrom, ops::Deref, path::PathBuf};
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{head, options},
+};
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use color_eyre::{
+    eyre::{eyre, OptionExt},
+    Result,
+};
+use futures::StreamExt as _;
+use rand::distr::{Alphanumeric, SampleString as _};
+use surrealdb::RecordId;
+use tokio::io::{AsyncSeekExt as _, AsyncWriteExt as _};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+use crate::{
+    api_token::RouteScope,
+    axum_error::AxumResult,
+    images::{self, remove_thumbnails},
+    quota,
+    routes::RouteType,
+    schema::{Created, File, PartialCreated, PartialFile, PartialUpload, Upload},
+    settings::{ArcSettings, Settings},
+    state::SurrealDb,
+    storage::Storage,
+    userid_extractor::SessionUserId,
+};
+
+use super::{
+    file::{blob_key, create_file, expiry, parse_flag, remove_blob},
+    shortcut::{self, PostShortcutOptions},
+    Route,
+};
+
+const PATH: &str = "/api/tus";
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+
+pub fn routes() -> Vec<Route> {
+    [
+        vec![(
+            RouteType::Undocumented((PATH, options(options_tus).post(post_upload)))
+                .scoped(RouteScope::Resource("file")),
+            true,
+        )],
+        by_id::routes(),
+    ]
+    .concat()
+}
+
+pub fn staging_path(settings: &Settings, id: &RecordId) -> PathBuf {
+    settings.tus.staging_path.join(id.key().to_string())
+}
+
+/// Returns a response if the client doesn't speak our version of the protocol
+fn check_version(headers: &HeaderMap) -> Option<Response> {
+    if headers
+        .get(TUS_RESUMABLE)
+        .is_some_and(|version| version == TUS_VERSION)
+    {
+        return None;
+    }
+
+    Some(
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [(TUS_VERSION_HEADER, TUS_VERSION)],
+            "Unsupported tus version",
+        )
+            .into_response(),
+    )
+}
+
+fn parse_u64_header(headers: &HeaderMap, name: HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Parses the `Upload-Metadata` header, which is a comma-separated list of keys with optional base64-encoded values
+fn parse_metadata(headers: &HeaderMap) -> Option<HashMap<String, String>> {
+    let Some(metadata) = headers.get(UPLOAD_METADATA) else {
+        return Some(HashMap::new());
+    };
+
+    metadata
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+            let value = String::from_utf8(BASE64_STANDARD.decode(value.trim()).ok()?).ok()?;
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+async fn options_tus() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_RESUMABLE, TUS_VERSION),
+            (TUS_VERSION_HEADER, TUS_VERSION),
+            (TUS_EXTENSION, TUS_EXTENSIONS),
+        ],
+    )
+}
+
+/// Creates a new upload (the `creation` extension)
+async fn post_upload(
+    State(db): State<SurrealDb>,
+    State(settings): State<ArcSettings>,
+    userid: SessionUserId,
+    headers: HeaderMap,
+) -> AxumResult<Response> {
+    if let Some(response) = check_version(&headers) {
+        return Ok(response);
+    }
+
+    let Some(length) = parse_u64_header(&headers, UPLOAD_LENGTH) else {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            [(TUS_RESUMABLE, TUS_VERSION)],
+            "Missing or invalid Upload-Length",
+        )
+            .into_response());
+    };
+
+    if let Some(limit) = quota::usage(&db, &settings.quotas, &userid)
+        .await?
+        .max_upload_size()
+        .filter(|limit| length > *limit)
+    {
+        return Ok((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            [(TUS_RESUMABLE, TUS_VERSION)],
+            quota::exceeded_message(limit),
+        )
+            .into_response());
+    }
+
+    let Some(metadata) = parse_metadata(&headers) else {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            [(TUS_RESUMABLE, TUS_VERSION)],
+            "Invalid Upload-Metadata",
+        )
+            .into_response());
+    };
+
+    let shortcuts: Vec<String> = metadata
+        .get("shortcuts")
+        .map(|shortcuts| {
+            shortcuts
+                .split(',')
+                .map(str::trim)
+                .filter(|shortcut| !shortcut.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let expires_in = match metadata.get("expires_in").map(|value| value.trim().parse()) {
+        Some(Ok(seconds)) => Some(seconds),
+        Some(Err(_)) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                "Invalid expires_in",
+            )
+                .into_response())
+        }
+        None => None,
+    };
+
+    let mut shortcut_options = PostShortcutOptions::default();
+
+    for (name, value) in &metadata {
+        if let Err(message) = shortcut_options.set_field(name, value) {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                message,
+            )
+                .into_response());
+        }
+    }
+
+    let shortcut_options = shortcut_options.into_options().await?;
+
+    let collisions = shortcut::find_collisions(&db, &shortcuts).await?;
+
+    if !collisions.is_empty() {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            [(TUS_RESUMABLE, TUS_VERSION)],
+            format!("Shortcuts already exist: {}", collisions.join(", ")),
+        )
+            .into_response());
+    }
+
+    let id = RecordId::from_table_key("upload", Alphanumeric.sample_string(&mut rand::rng(), 20));
+
+    tokio::fs::create_dir_all(&settings.tus.staging_path).await?;
+    tokio::fs::File::create(staging_path(&settings, &id)).await?;
+
+    let created_upload: Upload = db
+        .create(id)
+        .content(PartialUpload {
+            name: metadata
+                .get("filename")
+                .cloned()
+                .unwrap_or_else(|| "file".to_string()),
+            content_type: metadata
+                .get("filetype")
+                .cloned()
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+            length,
+            received: 0,
+            shortcuts,
+            expires_at: expiry(&settings.files, expires_in),
+            keep_metadata: metadata
+                .get("keep_metadata")
+                .is_some_and(|value| parse_flag(value)),
+            shortcut_options,
+        })
+        .await?
+        .ok_or_eyre("Failed to create upload")?;
+
+    if (db
+        .insert("created")
+        .relation(PartialCreated {
+            object: created_upload.id.clone(),
+            user: userid.deref().clone(),
+        })
+        .await? as Vec<Created>)
+        .is_empty()
+    {
+        delete_upload_record(&db, &settings, &created_upload.id).await?;
+        return Err(eyre!("Failed to create upload").into());
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (TUS_RESUMABLE, TUS_VERSION.to_string()),
+            (
+                header::LOCATION,
+                format!("{PATH}/{}", created_upload.id.key()),
+            ),
+        ],
+    )
+        .into_response())
+}
+
+async fn find_upload(db: &SurrealDb, user: &RecordId, id: RecordId) -> Result<Option<Upload>> {
+    Ok(db
+        .query("SELECT * FROM ONLY $upload WHERE array::any(array::matches(<-created<-user.id, $user))")
+        .bind(("upload", id))
+        .bind(("user", user.clone()))
+        .await?
+        .take(0)?)
+}
+
+/// Deletes an upload along with its partial data
+pub async fn delete_upload_record(
+    db: &SurrealDb,
+    settings: &Settings,
+    id: &RecordId,
+) -> Result<()> {
+    db.query("DELETE $upload<-created; DELETE $upload;")
+        .bind(("upload", id.clone()))
+        .await?
+        .check()?;
+
+    let path = staging_path(settings, id);
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        warn!(error = ?e, path = ?path, "Failed to remove partial upload");
+    }
+
+    Ok(())
+}
+
+/// Moves a complete upload to the blob storage and creates a file from it.
+///
+/// The upload is removed either way, since it can't be finished again. If the file can't be created
+/// because of the content or the shortlinks of the upload, a status and message for the client is
+/// returned instead.
+async fn finish_upload(
+    db: &SurrealDb,
+    storage: &Storage,
+    settings: &Settings,
+    user: &RecordId,
+    upload: Upload,
+) -> Result<Result<File, (StatusCode, String)>> {
+    let upload_id = upload.id.clone();
+    let result = store_upload(db, storage, settings, user, upload).await;
+
+    delete_upload_record(db, settings, &upload_id).await?;
+
+    result
+}
+
+/// Does the work of [`finish_upload`], removing what it stored if it fails
+async fn store_upload(
+    db: &SurrealDb,
+    storage: &Storage,
+    settings: &Settings,
+    user: &RecordId,
+    upload: Upload,
+) -> Result<Result<File, (StatusCode, String)>> {
+    // the shortlinks might have been taken since the upload was created
+    let collisions = shortcut::find_collisions(db, &upload.shortcuts).await?;
+
+    if !collisions.is_empty() {
+        return Ok(Err((
+            StatusCode::CONFLICT,
+            format!("Shortcuts already exist: {}", collisions.join(", ")),
+        )));
+    }
+
+    let mut shortcuts = upload.shortcuts;
+    if shortcuts.is_empty() {
+        shortcuts.push(shortcut::random_shortlink());
+    }
+
+    let id = RecordId::from_table_key("file", upload.id.key().clone());
+    let key = blob_key(&id);
+
+    let staged = tokio::fs::File::open(staging_path(settings, &upload.id)).await?;
+    if let Err(e) = storage.put(&key, ReaderStream::new(staged).boxed()).await {
+        remove_blob(storage, &key).await;
+        return Err(e);
+    }
+
+    let mut file = PartialFile {
+        name: upload.name,
+        content_type: upload.content_type,
+        size: upload.length,
+        expires_at: upload.expires_at,
+        width: None,
+        height: None,
+        thumbnails: Vec::new(),
+    };
+
+    if let Err(message) = images::process_upload(
+        storage,
+        &settings.images,
+        &key,
+        &mut file,
+        upload.keep_metadata,
+    )
+    .await
+    {
+        remove_blob(storage, &key).await;
+        return Ok(Err((StatusCode::UNPROCESSABLE_ENTITY, message.to_string())));
+    }
+
+    let thumbnails = file.thumbnails.clone();
+    match create_file(db, user, id, file, &shortcuts, &upload.shortcut_options).await {
+        Ok(file) => Ok(Ok(file)),
+        Err(e) => {
+            remove_blob(storage, &key).await;
+            remove_thumbnails(storage, &key, &thumbnails).await;
+            Err(e)
+        }
+    }
+}
+
+mod by_id {
+    use super::*;
+
+    const PATH: &str = "/api/tus/{id}";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(
+            RouteType::Undocumented((
+                PATH,
+                head(head_upload)
+                    .patch(patch_upload)
+                    .delete(delete_upload)
+                    .layer(DefaultBodyLimit::disable()),
+            ))
+            .scoped(RouteScope::Resource("file")),
+            true,
+        )]
+    }
+
+    /// Returns the current offset of an upload
+    async fn head_upload(
+        State(db): State<SurrealDb>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+    ) -> AxumResult<Response> {
+        if let Some(response) = check_version(&headers) {
+            return Ok(response);
+        }
+
+        let id = RecordId::from_table_key("upload", id);
+
+        Ok(match find_upload(&db, &userid, id).await? {
+            Some(upload) => (
+                StatusCode::OK,
+                [
+                    (TUS_RESUMABLE, TUS_VERSION.to_string()),
+                    (UPLOAD_OFFSET, upload.received.to_string()),
+                    (UPLOAD_LENGTH, upload.length.to_string()),
+                    (header::CACHE_CONTROL, "no-store".to_string()),
+                ],
+            )
+                .into_response(),
+            None => (
+                StatusCode::NOT_FOUND,
+                [
+                    (TUS_RESUMABLE, TUS_VERSION),
+                    (header::CACHE_CONTROL, "no-store"),
+                ],
+            )
+                .into_response(),
+        })
+    }
+
+    /// Appends data to an upload at the given offset
+    async fn patch_upload(
+        State(db): State<SurrealDb>,
+        State(settings): State<ArcSettings>,
+        State(storage): State<Storage>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+        body: Body,
+    ) -> AxumResult<Response> {
+        if let Some(response) = check_version(&headers) {
+            return Ok(response);
+        }
+
+        if headers
+            .get(header::CONTENT_TYPE)
+            .is_none_or(|content_type| content_type != OFFSET_OCTET_STREAM)
+        {
+            return Ok((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                "Content-Type must be application/offset+octet-stream",
+            )
+                .into_response());
+        }
+
+        let Some(offset) = parse_u64_header(&headers, UPLOAD_OFFSET) else {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                "Missing or invalid Upload-Offset",
+            )
+                .into_response());
+        };
+
+        let id = RecordId::from_table_key("upload", id);
+
+        let Some(upload) = find_upload(&db, &userid, id).await? else {
+            return Ok((StatusCode::NOT_FOUND, [(TUS_RESUMABLE, TUS_VERSION)]).into_response());
+        };
+
+        if offset != upload.received {
+            return Ok((
+                StatusCode::CONFLICT,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                "Upload-Offset does not match the current offset",
+            )
+                .into_response());
+        }
+
+        let mut staged = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(staging_path(&settings, &upload.id))
+            .await?;
+
+        // drop anything written after the last offset we managed to persist
+        staged.set_len(upload.received).await?;
+        staged.seek(SeekFrom::End(0)).await?;
+
+        let mut received = upload.received;
+        let mut overflow = false;
+        let mut body = body.into_data_stream();
+
+        while let Some(chunk) = body.next().await {
+            let Ok(chunk) = chunk else {
+                // the client went away, keep whatever we've got so far
+                break;
+            };
+
+            let remaining = (upload.length - received) as usize;
+            if chunk.len() > remaining {
+                overflow = true;
+            }
+
+            let chunk = &chunk[..chunk.len().min(remaining)];
+            staged.write_all(chunk).await?;
+            received += chunk.len() as u64;
+
+            if overflow {
+                break;
+            }
+        }
+
+        staged.flush().await?;
+        staged.sync_data().await?;
+
+        let updated: Option<Upload> = db
+            .query("UPDATE ONLY $upload SET received = $received WHERE received = $previous RETURN AFTER")
+            .bind(("upload", upload.id.clone()))
+            .bind(("received", received))
+            .bind(("previous", upload.received))
+            .await?
+            .take(0)?;
+
+        let Some(updated) = updated else {
+            return Ok((
+                StatusCode::CONFLICT,
+                [(TUS_RESUMABLE, TUS_VERSION)],
+                "The upload was modified concurrently",
+            )
+                .into_response());
+        };
+
+        if overflow {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                [
+                    (TUS_RESUMABLE, TUS_VERSION.to_string()),
+                    (UPLOAD_OFFSET, received.to_string()),
+                ],
+                "Upload exceeds Upload-Length",
+            )
+                .into_response());
+        }
+
+        let mut response = (
+            StatusCode::NO_CONTENT,
+            [
+                (TUS_RESUMABLE, TUS_VERSION.to_string()),
+                (UPLOAD_OFFSET, received.to_string()),
+            ],
+        )
+            .into_response();
+
+        if updated.received == updated.length {
+            let file = match finish_upload(&db, &storage, &settings, &userid, updated).await? {
+                Ok(file) => file,
+                Err((status, message)) => {
+                    return Ok((status, [(TUS_RESUMABLE, TUS_VERSION)], message).into_response());
+                }
+            };
+
+            // not part of tus, but lets clients find the file they've just uploaded
+            response.headers_mut().insert(
+                header::CONTENT_LOCATION,
+                HeaderValue::from_str(&format!("/api/file/{}", file.id.key()))?,
+            );
+        }
+
+        Ok(response)
+    }
+
+    /// Cancels an upload and removes its data (the `termination` extension)
+    async fn delete_upload(
+        State(db): State<SurrealDb>,
+        State(settings): State<ArcSettings>,
+        userid: SessionUserId,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+    ) -> AxumResult<Response> {
+        if let Some(response) = check_version(&headers) {
+            return Ok(response);
+        }
+
+        let id = RecordId::from_table_key("upload", id);
+
+        let Some(upload) = find_upload(&db, &userid, id).await? else {
+            return Ok((StatusCode::NOT_FOUND, [(TUS_RESUMABLE, TUS_VERSION)]).into_response());
+        };
+
+        delete_upload_record(&db, &settings, &upload.id).await?;
+
+        Ok((StatusCode::NO_CONTENT, [(TUS_RESUMABLE, TUS_VERSION)]).into_response())
+    }
+}
@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+use utoipa_axum::routes;
+
+use crate::{
+    api_token::replace_token, api_token::RouteScope, axum_error::AxumResult, routes::RouteType,
+    settings::ArcSettings, state::SurrealDb, userid_extractor::SessionUserId,
+};
+
+use super::Route;
+
+pub fn routes() -> Vec<Route> {
+    vec![(
+        RouteType::OpenApi(routes!(post_sharex_config, post_flameshot_script))
+            .scoped(RouteScope::Resource("token")),
+        true,
+    )]
+}
+
+fn public_url(settings: &ArcSettings) -> String {
+    settings
+        .general
+        .public_url
+        .to_string()
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn attachment(filename: &str) -> String {
+    format!("attachment; filename=\"{filename}\"")
+}
+
+#[derive(Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SharexDestination {
+    /// Image and file uploads
+    File,
+    /// URL shortening
+    Link,
+    /// Text uploads, stored as pastes
+    Paste,
+}
+
+/// Generate a ShareX custom uploader (`.sxcu`) for your account.
+///
+/// Every destination gets its own API token. Generating a config again revokes the token of the previously generated one.
+#[utoipa::path(
+    method(post),
+    path = "/api/uploader/sharex/{destination}",
+    params(
+        ("destination" = SharexDestination, Path, description = "What the uploader should be used for")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str, content_type = "application/json")
+    )
+)]
+async fn post_sharex_config(
+    State(db): State<SurrealDb>,
+    State(settings): State<ArcSettings>,
+    userid: SessionUserId,
+    Path(destination): Path<SharexDestination>,
+) -> AxumResult<impl IntoResponse> {
+    let public_url = public_url(&settings);
+    let host = settings.general.public_url.host().unwrap_or("ShareOxide");
+
+    let (kind, destination_type) = match destination {
+        SharexDestination::File => ("file", "ImageUploader, FileUploader"),
+        SharexDestination::Link => ("link", "URLShortener"),
+        SharexDestination::Paste => ("paste", "TextUploader"),
+    };
+
+    let token = replace_token(
+        &db,
+        &userid,
+        &format!("ShareX ({kind})"),
+        &[&format!("{kind}:write")],
+    )
+    .await?;
+
+    let mut config = json!({
+        "Version": "15.0.0",
+        "Name": format!("ShareOxide {kind} ({host})"),
+        "DestinationType": destination_type,
+        "RequestMethod": "POST",
+        "RequestURL": format!("{public_url}/api/{kind}"),
+        "Headers": {
+            "Authorization": format!("Bearer {token}"),
+        },
+        "URL": format!("{public_url}/{{json:shortcuts[0]}}"),
+        "ErrorMessage": "{response}",
+    });
+
+    match destination {
+        SharexDestination::File => {
+            config["Body"] = json!("MultipartFormData");
+            config["FileFormName"] = json!("file");
+        }
+        SharexDestination::Link => {
+            config["Body"] = json!("JSON");
+            config["Data"] = json!(r#"{"url":"{input}","shortcuts":null}"#);
+        }
+        SharexDestination::Paste => {
+            config["Body"] = json!("JSON");
+            config["Data"] = json!(r#"{"content":"{input}","shortcuts":null}"#);
+        }
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                attachment(&format!("shareoxide-{kind}.sxcu")),
+            ),
+        ],
+        serde_json::to_string_pretty(&config)?,
+    ))
+}
+
+/// Generate a shell script that uploads files or Flameshot screenshots to your account.
+///
+/// Run it without arguments to take a screenshot with Flameshot, or pass a file to upload it. The link gets printed and copied to the clipboard.
+/// Generating the script again revokes the API token embedded in the previous one.
+#[utoipa::path(
+    method(post),
+    path = "/api/uploader/flameshot",
+    responses(
+        (status = OK, description = "Success", body = str, content_type = "text/x-shellscript")
+    )
+)]
+async fn post_flameshot_script(
+    State(db): State<SurrealDb>,
+    State(settings): State<ArcSettings>,
+    userid: SessionUserId,
+) -> AxumResult<impl IntoResponse> {
+    let token = replace_token(&db, &userid, "Flameshot", &["file:write"]).await?;
+
+    let script = include_str!("uploader/flameshot.sh")
+        .replace("@PUBLIC_URL@", &public_url(&settings))
+        .replace("@TOKEN@", &token);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/x-shellscript".to_string()),
+            (header::CONTENT_DISPOSITION, attachment("shareoxide.sh")),
+        ],
+        script,
+    ))
+}
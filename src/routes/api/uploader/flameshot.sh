@@ -0,0 +1,44 @@
+#!/bin/sh
+# Uploads a file to ShareOxide and copies the short link to the clipboard.
+# Without arguments, takes a screenshot with Flameshot and uploads it instead.
+#
+# Requires curl and jq, plus wl-copy or xclip for the clipboard.
+
+set -eu
+
+PUBLIC_URL='@PUBLIC_URL@'
+TOKEN='@TOKEN@'
+
+if [ "$#" -gt 0 ]; then
+    file="$1"
+else
+    file="$(mktemp --suffix=.png)"
+    trap 'rm -f "$file"' EXIT
+
+    flameshot gui --raw > "$file"
+
+    # the capture was cancelled
+    [ -s "$file" ] || exit 0
+fi
+
+shortcut="$(
+    curl --fail-with-body --silent --show-error \
+        --header "Authorization: Bearer $TOKEN" \
+        --form "file=@$file" \
+        "$PUBLIC_URL/api/file" |
+        jq --raw-output '.shortcuts[0]'
+)"
+
+link="$PUBLIC_URL/$shortcut"
+
+if command -v wl-copy > /dev/null 2>&1 && [ -n "${WAYLAND_DISPLAY:-}" ]; then
+    printf '%s' "$link" | wl-copy
+elif command -v xclip > /dev/null 2>&1; then
+    printf '%s' "$link" | xclip -selection clipboard
+fi
+
+if command -v notify-send > /dev/null 2>&1; then
+    notify-send "ShareOxide" "Uploaded to $link"
+fi
+
+echo "$link"
@@ -1,5 +1,6 @@
 mod home;
 mod js;
+pub mod paste;
 mod styles;
 
 use maud::{html, Markup, Render, DOCTYPE};
@@ -10,7 +11,7 @@ pub fn routes() -> Vec<Route> {
     [styles::routes(), home::routes(), js::routes()].concat()
 }
 
-fn page(content: impl Render, title: Option<&str>) -> Markup {
+pub fn page(content: impl Render, title: Option<&str>) -> Markup {
     html! {
         (DOCTYPE)
         html lang="en" {
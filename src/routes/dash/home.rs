@@ -5,7 +5,7 @@ use axum_oidc::OidcClaims;
 use maud::{html, Markup};
 
 use crate::{
-    axum_error::AxumResult, routes::{api::link::GetLinkResponse, dash::page, RouteType}, state::SurrealDb, userid_extractor::SessionUserId, GroupClaims
+    axum_error::AxumResult, routes::{api::{file::GetFileResponse, link::GetLinkResponse}, dash::page, RouteType}, state::SurrealDb, userid_extractor::SessionUserId, GroupClaims
 };
 
 use super::Route;
@@ -24,7 +24,18 @@ async fn get_dash_home(
 
     let links: Vec<GetLinkResponse> = 
         db.query(
-            "SELECT VALUE ->created->link.{id, url, shortcuts: <-expands_to<-shortcut.shortlink} FROM ONLY $user",
+            "
+                LET $links = array::union($user->created->link, $user->member_of->oidc_group->owns->link);
+                SELECT id, url, <-expands_to<-shortcut.shortlink AS shortcuts FROM $links;
+            ",
+        )
+        .bind(("user", userid.deref().clone()))
+        .await?
+        .take(1)?;
+
+    let files: Vec<GetFileResponse> = 
+        db.query(
+            "SELECT VALUE ->created->file.{id, name, content_type, size, expires_at, width, height, thumbnails, shortcuts: <-expands_to<-shortcut.shortlink} FROM ONLY $user",
         )
         .bind(("user", userid.deref().clone()))
         .await?
@@ -60,6 +71,52 @@ async fn get_dash_home(
                     }
                 }
             }
+
+            h2 { "Your Files" }
+
+            table id="files" {
+                thead {
+                    tr {
+                        th { "Preview" }
+                        th { "Name" }
+                        th { "Short link" }
+                    }
+                }
+                tbody {
+                    @for file in files {
+                        @let shortcut = file.shortcuts.first();
+                        tr {
+                            td {
+                                @if let (Some(shortcut), Some(size)) = (shortcut, file.thumbnails.iter().min()) {
+                                    img class="thumbnail" src=(format!("/{shortcut}/thumbnail/{size}")) alt=(file.name);
+                                }
+                            }
+                            td {
+                                @if let Some(shortcut) = shortcut {
+                                    a href=(format!("/{shortcut}")) { (file.name) }
+                                } @else {
+                                    (file.name)
+                                }
+                                @if let (Some(width), Some(height)) = (file.width, file.height) {
+                                    " (" (width) "×" (height) ")"
+                                }
+                            }
+                            td { pre { (file.shortcuts.join(", ")) } }
+                        }
+                    }
+                }
+            }
+
+            h2 { "Uploaders" }
+
+            p { "Downloading a config creates a new API token for it and revokes the one from the previous download." }
+
+            div class="uploaders" {
+                form method="post" action="/api/uploader/sharex/file" { button type="submit" { "ShareX (files)" } }
+                form method="post" action="/api/uploader/sharex/link" { button type="submit" { "ShareX (links)" } }
+                form method="post" action="/api/uploader/sharex/paste" { button type="submit" { "ShareX (text)" } }
+                form method="post" action="/api/uploader/flameshot" { button type="submit" { "Flameshot / shell script" } }
+            }
         },
         None,
     ))
@@ -0,0 +1,48 @@
+use std::sync::LazyLock;
+
+use color_eyre::Result;
+use maud::{html, Markup, PreEscaped};
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::highlighted_html_for_string,
+    parsing::SyntaxSet,
+};
+
+use crate::{routes::dash::page, schema::Paste};
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove("base16-ocean.dark")
+        .expect("default themes should include base16-ocean.dark")
+});
+
+/// Renders a paste as a page with server-side syntax highlighting
+pub fn render_paste(paste: &Paste, raw_url: &str) -> Result<Markup> {
+    let syntax = match &paste.language {
+        Some(language) => SYNTAX_SET.find_syntax_by_token(language),
+        None => SYNTAX_SET.find_syntax_by_first_line(&paste.content),
+    }
+    .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+
+    let highlighted = highlighted_html_for_string(&paste.content, &SYNTAX_SET, syntax, &THEME)?;
+
+    Ok(page(
+        html! {
+            @if let Some(title) = &paste.title {
+                h1 { (title) }
+            }
+
+            div .paste {
+                p {
+                    (syntax.name) " - "
+                    a href=(raw_url) { "Raw" }
+                }
+
+                (PreEscaped(highlighted))
+            }
+        },
+        paste.title.as_deref(),
+    ))
+}
@@ -1,21 +1,186 @@
+mod analytics;
+mod passthrough;
+mod preview;
+mod rotation;
+mod rules;
+mod schedule;
+mod template;
+mod unlock;
+mod visits;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Redirect},
+    body::Body,
+    extract::{Path, RawQuery, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Form,
 };
+use color_eyre::Result;
+use serde::Deserialize;
+use surrealdb::RecordId;
+use tower_sessions::Session;
+use utoipa::ToSchema;
 use utoipa_axum::routes;
 
-use crate::{axum_error::AxumResult, routes::RouteType, state::SurrealDb};
+use crate::{
+    axum_error::AxumResult,
+    images::{thumbnail_format, thumbnail_key},
+    password::verify_password,
+    routes::RouteType,
+    schema::{File, Link, Paste, RedirectRule, Rotation},
+    settings::{ArcSettings, RedirectStatus, Settings},
+    state::SurrealDb,
+    storage::Storage,
+};
+
+use super::{
+    api::{
+        file::{blob_key, file_response, is_expired},
+        shortcut::delete_object,
+    },
+    dash::paste::render_paste,
+    Route,
+};
 
-use super::Route;
+use passthrough::Passthrough;
 
 const PATH: &str = "/{shortlink}";
+const PASSTHROUGH_PATH: &str = "/{shortlink}/{*path}";
+
+const GONE_MESSAGE: &str = "This link has reached its visit limit";
 
 pub fn routes() -> Vec<Route> {
-    vec![(RouteType::OpenApi(routes!(get_shortcut_redirect)), false)]
+    [
+        vec![(
+            RouteType::OpenApi(routes!(get_shortcut_redirect, post_shortcut_unlock)),
+            false,
+        )],
+        vec![(
+            RouteType::OpenApi(routes!(get_shortcut_redirect_passthrough)),
+            false,
+        )],
+        raw::routes(),
+        thumbnail::routes(),
+        preview::routes(),
+    ]
+    .concat()
+}
+
+#[derive(Deserialize)]
+struct Target {
+    /// The shortcut itself
+    id: RecordId,
+
+    /// The first object the shortcut expands to
+    object: Option<RecordId>,
+
+    /// All objects the shortcut expands to, with their weights
+    #[serde(default)]
+    variants: Vec<rotation::Variant>,
+
+    #[serde(default)]
+    rotation: Rotation,
+
+    #[serde(default)]
+    rules: Vec<RedirectRule>,
+
+    password: Option<String>,
+
+    max_visits: Option<u64>,
+
+    #[serde(default)]
+    visits: u64,
+
+    #[serde(default)]
+    burn_after_read: bool,
+
+    /// Whether the shortcut's `not_before` is still in the future
+    #[serde(default)]
+    pending: bool,
+
+    /// Whether the shortcut's `expires_at` has passed
+    #[serde(default)]
+    expired: bool,
+
+    /// The `not_before` of the shortcut, formatted for humans
+    active_from: Option<String>,
+
+    redirect_status: Option<RedirectStatus>,
+
+    /// Whether the rest of the request is passed through to the destination link
+    #[serde(default)]
+    passthrough: bool,
+}
+
+impl Target {
+    /// Whether the shortcut has been visited as many times as it's allowed to
+    fn exhausted(&self) -> bool {
+        self.max_visits.is_some_and(|max| self.visits >= max)
+            || (self.burn_after_read && self.visits > 0)
+    }
+
+    /// Whether the shortcut only works a limited number of times
+    fn limited(&self) -> bool {
+        self.max_visits.is_some() || self.burn_after_read
+    }
+}
+
+/// Finds the shortcut with the given shortlink along with the object it expands to
+async fn find_target(db: &SurrealDb, shortlink: String) -> Result<Option<Target>> {
+    Ok(db
+        .query(
+            "
+                SELECT
+                    id,
+                    array::first(->expands_to.out) AS object,
+                    ->expands_to.{object: out, weight} AS variants,
+                    rotation,
+                    rules,
+                    password,
+                    max_visits,
+                    visits,
+                    burn_after_read,
+                    not_before != NONE AND not_before > time::now() AS pending,
+                    expires_at != NONE AND expires_at < time::now() AS expired,
+                    IF not_before != NONE THEN time::format(not_before, '%Y-%m-%d %H:%M UTC') END AS active_from,
+                    redirect_status,
+                    passthrough
+                FROM ONLY shortcut WHERE shortlink = $shortlink
+            ",
+        )
+        .bind(("shortlink", shortlink))
+        .await?
+        .take(0)?)
+}
+
+/// Returns the unlock form if the shortcut is password-protected and hasn't been unlocked in this session
+async fn locked_response(
+    session: &Session,
+    target: &Target,
+    shortlink: &str,
+) -> Result<Option<Response>> {
+    if target.password.is_none() || unlock::is_unlocked(session, &target.id).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        (
+            StatusCode::UNAUTHORIZED,
+            unlock::render_unlock_form(shortlink, false),
+        )
+            .into_response(),
+    ))
 }
 
-/// Redirects you to the destination of the shortcut
+/// Redirects you to the destination of the shortcut, downloads the file or shows the paste it points to
+///
+/// Password-protected shortcuts show a form asking for the password instead, until they're unlocked.
+/// Shortcuts with a visit limit stop working once it's reached, and burn-after-read ones delete what they point to after the first visit.
+/// Scheduled shortcuts only work between their `not_before` and `expires_at`.
+/// Every successful visit is logged for the shortcut's statistics, except for burn-after-read shortcuts, which are deleted right away.
+/// Links are redirected to with the shortcut's redirect status, or the instance default.
+/// Named placeholders of go-link templates are filled in from the query parameters.
+/// The first redirect rule the visitor meets the conditions of picks the link, otherwise one of the shortcut's destinations is used.
 #[utoipa::path(
     method(get),
     path = PATH,
@@ -23,22 +188,400 @@ pub fn routes() -> Vec<Route> {
         ("shortlink" = String, Path, description = "The short link to redirect to")
     ),
     responses(
-        (status = OK, description = "Success", body = str)
+        (status = OK, description = "Success", body = str),
+        (status = TEMPORARY_REDIRECT, description = "The shortcut points to a link. Depending on the shortcut, 301, 302 or 308 can be used instead"),
+        (status = BAD_REQUEST, description = "A placeholder of the go-link template is missing a value and the link has no fallback", body = str),
+        (status = UNAUTHORIZED, description = "The shortcut is password-protected", body = str, content_type = "text/html"),
+        (status = FORBIDDEN, description = "The shortcut isn't active yet", body = str, content_type = "text/html"),
+        (status = GONE, description = "The shortcut has expired or reached its visit limit", body = str)
     )
 )]
 async fn get_shortcut_redirect(
     State(db): State<SurrealDb>,
+    State(storage): State<Storage>,
+    State(settings): State<ArcSettings>,
+    session: Session,
+    visit: analytics::VisitInfo,
+    Path(shortlink): Path<String>,
+    RawQuery(query): RawQuery,
+) -> AxumResult<Response> {
+    let passthrough = Passthrough { path: None, query };
+
+    follow_shortcut(
+        &db,
+        &storage,
+        &settings,
+        &session,
+        visit,
+        shortlink,
+        passthrough,
+    )
+    .await
+}
+
+/// Follows a shortcut with extra path segments, which fill in the placeholders of go-link templates
+/// or are appended to the destination of shortcuts with passthrough
+///
+/// Other shortcuts respond with 404.
+/// `raw` and `thumbnail/{size}` can't be passed through, as they're routes of their own.
+#[utoipa::path(
+    method(get),
+    path = PASSTHROUGH_PATH,
+    params(
+        ("shortlink" = String, Path, description = "The short link to redirect to"),
+        ("path" = String, Path, description = "The path to append to the destination")
+    ),
+    responses(
+        (status = TEMPORARY_REDIRECT, description = "Success. Depending on the shortcut, 301, 302 or 308 can be used instead"),
+        (status = UNAUTHORIZED, description = "The shortcut is password-protected", body = str, content_type = "text/html"),
+        (status = FORBIDDEN, description = "The shortcut isn't active yet", body = str, content_type = "text/html"),
+        (status = BAD_REQUEST, description = "A placeholder of the go-link template is missing a value and the link has no fallback", body = str),
+        (status = NOT_FOUND, description = "The shortcut doesn't exist, or it neither is a template nor has passthrough enabled", body = str),
+        (status = GONE, description = "The shortcut has expired or reached its visit limit", body = str)
+    )
+)]
+async fn get_shortcut_redirect_passthrough(
+    State(db): State<SurrealDb>,
+    State(storage): State<Storage>,
+    State(settings): State<ArcSettings>,
+    session: Session,
+    visit: analytics::VisitInfo,
+    Path((shortlink, path)): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+) -> AxumResult<Response> {
+    let passthrough = Passthrough {
+        path: Some(path),
+        query,
+    };
+
+    follow_shortcut(
+        &db,
+        &storage,
+        &settings,
+        &session,
+        visit,
+        shortlink,
+        passthrough,
+    )
+    .await
+}
+
+async fn follow_shortcut(
+    db: &SurrealDb,
+    storage: &Storage,
+    settings: &Settings,
+    session: &Session,
+    visit: analytics::VisitInfo,
+    shortlink: String,
+    passthrough: Passthrough,
+) -> AxumResult<Response> {
+    let Some(target) = find_target(db, shortlink.clone()).await? else {
+        return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+    };
+
+    if let Some(response) = schedule::inactive_response(&target) {
+        return Ok(response);
+    }
+
+    if let Some(response) = locked_response(session, &target, &shortlink).await? {
+        return Ok(response);
+    }
+
+    let Some(object) = rules::matching_link(&target.rules, &visit)
+        .or_else(|| rotation::pick(&target.variants, target.rotation, target.visits))
+        .cloned()
+    else {
+        return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+    };
+
+    // where a link leads depends on the rest of the request, so it's worked out before counting the visit
+    let destination = if object.table() == "link" {
+        let Some(link) = db.select::<Option<Link>>(object.clone()).await? else {
+            return Ok((StatusCode::NOT_FOUND, "Link not found").into_response());
+        };
+
+        Some(if template::is_template(&link.url) {
+            match (template::fill(&link.url, &passthrough), link.fallback_url) {
+                (Ok(url), _) => url,
+                (Err(_), Some(fallback_url)) => fallback_url,
+                (Err(missing), None) => {
+                    return Ok((
+                        StatusCode::BAD_REQUEST,
+                        format!("This link needs a value for {{{missing}}}"),
+                    )
+                        .into_response());
+                }
+            }
+        } else if target.passthrough {
+            passthrough.apply(&link.url)?
+        } else if passthrough.path.is_some() {
+            return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+        } else {
+            link.url
+        })
+    } else if passthrough.path.is_some() {
+        return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+    } else {
+        None
+    };
+
+    // expired files are about to be deleted, so they don't use up a visit
+    if object.table() == "file" && is_expired(db, &object).await? {
+        return Ok((StatusCode::GONE, "File has expired").into_response());
+    }
+
+    if !count_visit(db, &target, &object, visit).await? {
+        return Ok((StatusCode::GONE, GONE_MESSAGE).into_response());
+    }
+
+    if let Some(url) = destination {
+        if target.burn_after_read {
+            delete_object(db, &object).await?;
+        }
+
+        let status = target
+            .redirect_status
+            .unwrap_or(settings.links.default_redirect_status);
+
+        return Ok((status.status_code(), [(header::LOCATION, url)]).into_response());
+    }
+
+    match object.table() {
+        "file" => match db.select::<Option<File>>(object.clone()).await? {
+            Some(file) if target.burn_after_read => Ok(visits::burn_file_after(
+                db,
+                storage,
+                object,
+                file_response(storage, file).await?,
+            )),
+            Some(file) => file_response(storage, file).await,
+            None => Ok((StatusCode::NOT_FOUND, "File not found").into_response()),
+        },
+        "paste" => match db.select::<Option<Paste>>(object.clone()).await? {
+            Some(paste) => {
+                let page = render_paste(&paste, &format!("/{shortlink}/raw"))?;
+
+                if target.burn_after_read {
+                    delete_object(db, &object).await?;
+                }
+
+                Ok(page.into_response())
+            }
+            None => Ok((StatusCode::NOT_FOUND, "Paste not found").into_response()),
+        },
+        _ => Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response()),
+    }
+}
+
+/// Counts and logs a visit of the shortcut that led to `object`, unless the shortcut has run out of visits.
+///
+/// Visits of burn-after-read shortcuts are archived instead, as the shortcut and its log are deleted
+/// along with the object right after.
+async fn count_visit(
+    db: &SurrealDb,
+    target: &Target,
+    object: &RecordId,
+    visit: analytics::VisitInfo,
+) -> Result<bool> {
+    if !visits::record_visit(db, &target.id).await? {
+        return Ok(false);
+    }
+
+    if target.burn_after_read {
+        analytics::archive_visit(db, &target.id, object, visit).await;
+    } else {
+        analytics::spawn_log_visit(db, &target.id, object, visit);
+    }
+
+    Ok(true)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UnlockForm {
+    password: String,
+}
+
+/// Unlocks a password-protected shortcut for the current session and takes you back to it
+#[utoipa::path(
+    method(post),
+    path = PATH,
+    params(
+        ("shortlink" = String, Path, description = "The short link to unlock")
+    ),
+    request_body(content = UnlockForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = SEE_OTHER, description = "The password is correct"),
+        (status = UNAUTHORIZED, description = "The password is wrong", body = str, content_type = "text/html")
+    )
+)]
+async fn post_shortcut_unlock(
+    State(db): State<SurrealDb>,
+    session: Session,
     Path(shortlink): Path<String>,
+    Form(form): Form<UnlockForm>,
 ) -> AxumResult<impl IntoResponse> {
-    match db
-        .query(
-            "SELECT VALUE ->expands_to->link.url FROM ONLY shortcut WHERE shortlink = $shortlink",
+    let Some(target) = find_target(&db, shortlink.clone()).await? else {
+        return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+    };
+
+    let Some(hash) = target.password else {
+        return Ok(Redirect::to(&format!("/{shortlink}")).into_response());
+    };
+
+    // Argon2 is deliberately slow, so keep it off the async workers
+    let correct =
+        tokio::task::spawn_blocking(move || verify_password(&hash, &form.password)).await?;
+
+    if !correct {
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            unlock::render_unlock_form(&shortlink, true),
         )
-        .bind(("shortlink", shortlink))
-        .await?
-        .take::<Option<String>>(0)?
-    {
-        Some(url) => Ok(Redirect::temporary(url.as_str()).into_response()),
-        None => Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response()),
+            .into_response());
+    }
+
+    unlock::unlock(&session, &target.id).await?;
+
+    Ok(Redirect::to(&format!("/{shortlink}")).into_response())
+}
+
+mod raw {
+    use super::*;
+
+    const PATH: &str = "/{shortlink}/raw";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(RouteType::OpenApi(routes!(get_shortcut_raw)), false)]
+    }
+
+    /// Returns the content of the paste the shortcut points to as plain text
+    ///
+    /// This counts as a visit of the shortcut, so burn-after-read pastes are deleted afterwards.
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        params(
+            ("shortlink" = String, Path, description = "The short link of the paste")
+        ),
+        responses(
+            (status = OK, description = "Success", body = str, content_type = "text/plain"),
+            (status = GONE, description = "The shortcut has expired or reached its visit limit", body = str)
+        )
+    )]
+    async fn get_shortcut_raw(
+        State(db): State<SurrealDb>,
+        session: Session,
+        visit: analytics::VisitInfo,
+        Path(shortlink): Path<String>,
+    ) -> AxumResult<impl IntoResponse> {
+        let Some(target) = find_target(&db, shortlink.clone()).await? else {
+            return Ok((StatusCode::NOT_FOUND, "Paste not found").into_response());
+        };
+
+        if let Some(response) = schedule::inactive_response(&target) {
+            return Ok(response);
+        }
+
+        if let Some(response) = locked_response(&session, &target, &shortlink).await? {
+            return Ok(response);
+        }
+
+        let Some(object) = target
+            .object
+            .clone()
+            .filter(|object| object.table() == "paste")
+        else {
+            return Ok((StatusCode::NOT_FOUND, "Paste not found").into_response());
+        };
+
+        let Some(paste) = db.select::<Option<Paste>>(object.clone()).await? else {
+            return Ok((StatusCode::NOT_FOUND, "Paste not found").into_response());
+        };
+
+        if !count_visit(&db, &target, &object, visit).await? {
+            return Ok((StatusCode::GONE, GONE_MESSAGE).into_response());
+        }
+
+        if target.burn_after_read {
+            delete_object(&db, &object).await?;
+        }
+
+        Ok((
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            paste.content,
+        )
+            .into_response())
+    }
+}
+
+mod thumbnail {
+    use super::*;
+
+    const PATH: &str = "/{shortlink}/thumbnail/{size}";
+
+    pub fn routes() -> Vec<Route> {
+        vec![(RouteType::OpenApi(routes!(get_shortcut_thumbnail)), false)]
+    }
+
+    /// Returns a thumbnail of the image the shortcut points to
+    ///
+    /// Thumbnails aren't available through shortcuts with a visit limit, as they'd show the image without using up a visit.
+    #[utoipa::path(
+        method(get),
+        path = PATH,
+        params(
+            ("shortlink" = String, Path, description = "The short link of the image"),
+            ("size" = u32, Path, description = "The size of the thumbnail, as listed in the `thumbnails` of the file")
+        ),
+        responses(
+            (status = OK, description = "Success", content_type = "image/*"),
+            (status = NOT_FOUND, description = "The shortcut or thumbnail doesn't exist, or the shortcut has a visit limit", body = str)
+        )
+    )]
+    async fn get_shortcut_thumbnail(
+        State(db): State<SurrealDb>,
+        State(storage): State<Storage>,
+        session: Session,
+        Path((shortlink, size)): Path<(String, u32)>,
+    ) -> AxumResult<impl IntoResponse> {
+        let Some(target) = find_target(&db, shortlink.clone()).await? else {
+            return Ok((StatusCode::NOT_FOUND, "File not found").into_response());
+        };
+
+        if let Some(response) = schedule::inactive_response(&target) {
+            return Ok(response);
+        }
+
+        if let Some(response) = locked_response(&session, &target, &shortlink).await? {
+            return Ok(response);
+        }
+
+        if target.limited() {
+            return Ok((StatusCode::NOT_FOUND, "Thumbnail not found").into_response());
+        }
+
+        let Some(object) = target.object.filter(|object| object.table() == "file") else {
+            return Ok((StatusCode::NOT_FOUND, "File not found").into_response());
+        };
+
+        if is_expired(&db, &object).await? {
+            return Ok((StatusCode::GONE, "File has expired").into_response());
+        }
+
+        match db.select::<Option<File>>(object).await? {
+            Some(file) if file.thumbnails.contains(&size) => Ok((
+                [(
+                    header::CONTENT_TYPE,
+                    thumbnail_format(&file.content_type).to_mime_type(),
+                )],
+                Body::from_stream(
+                    storage
+                        .get(&thumbnail_key(&blob_key(&file.id), size))
+                        .await?,
+                ),
+            )
+                .into_response()),
+            _ => Ok((StatusCode::NOT_FOUND, "Thumbnail not found").into_response()),
+        }
     }
 }
@@ -0,0 +1,276 @@
+//! Click analytics: every visit of a shortcut is logged along with where it came from.
+//!
+//! Client IPs are anonymized before they're stored, by dropping the host part of the address.
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderName, Uri},
+};
+use color_eyre::{
+    eyre::{eyre, ContextCompat},
+    Result,
+};
+use surrealdb::RecordId;
+use tracing::error;
+
+use crate::{
+    schema::{Logged, PartialLogged, PartialVisit, Visit},
+    state::{AppState, SurrealDb},
+};
+
+/// Longest user agent that's stored, in characters
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// What's known about a visitor of a shortcut
+#[derive(Debug, Clone)]
+pub struct VisitInfo {
+    /// The host of the page the visitor came from
+    pub referrer: Option<String>,
+
+    pub user_agent: Option<String>,
+
+    /// The anonymized IP address of the visitor
+    pub ip: Option<IpAddr>,
+
+    /// The visitor's preferred language from `Accept-Language`, in lowercase
+    pub language: Option<String>,
+}
+
+impl FromRequestParts<AppState> for VisitInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let ip = if state.settings.general.trust_proxy_headers {
+            forwarded_for(&parts.headers).or(peer)
+        } else {
+            peer
+        };
+
+        Ok(Self {
+            referrer: header_str(&parts.headers, header::REFERER)
+                .and_then(|referer| referer.parse::<Uri>().ok())
+                .and_then(|referer| referer.host().map(str::to_lowercase)),
+            user_agent: header_str(&parts.headers, header::USER_AGENT)
+                .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            ip: ip.map(anonymize_ip),
+            language: header_str(&parts.headers, header::ACCEPT_LANGUAGE)
+                .and_then(preferred_language),
+        })
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns the client address from the `X-Forwarded-For` header set by a reverse proxy
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    header_str(headers, HeaderName::from_static("x-forwarded-for"))?
+        .split(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns the language with the highest quality value from an `Accept-Language` header
+fn preferred_language(header: &str) -> Option<String> {
+    let mut best: Option<(&str, f32)> = None;
+
+    for entry in header.split(',') {
+        let mut parts = entry.split(';');
+        let language = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|parameter| parameter.trim().strip_prefix("q="))
+            .and_then(|quality| quality.parse().ok())
+            .unwrap_or(1.0);
+
+        if language.is_empty() || language == "*" || quality <= 0.0 {
+            continue;
+        }
+
+        // the first of several languages with the same quality wins
+        if best.is_none_or(|(_, best)| quality > best) {
+            best = Some((language, quality));
+        }
+    }
+
+    best.map(|(language, _)| language.to_lowercase())
+}
+
+/// Keeps only the network part of an address: the first 24 bits of IPv4 addresses and the first
+/// 48 bits of IPv6 ones
+fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Ipv4Addr::new(a, b, c, 0).into()
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0).into()
+        }
+    }
+}
+
+/// Stores a visit of the shortcut that sent the visitor to `variant`
+async fn log_visit(
+    db: &SurrealDb,
+    shortcut: &RecordId,
+    variant: &RecordId,
+    info: VisitInfo,
+) -> Result<()> {
+    let visit: Visit = db
+        .create("visit")
+        .content(PartialVisit {
+            referrer: info.referrer,
+            user_agent: info.user_agent,
+            ip: info.ip.map(|ip| ip.to_string()),
+            variant: Some(variant.clone()),
+        })
+        .await?
+        .wrap_err("Failed to log visit")?;
+
+    if (db
+        .insert("logged")
+        .relation(PartialLogged {
+            shortcut: shortcut.clone(),
+            visit: visit.id,
+        })
+        .await? as Vec<Logged>)
+        .is_empty()
+    {
+        return Err(eyre!("Failed to log visit"));
+    }
+
+    Ok(())
+}
+
+/// Logs a visit in the background, so that the visitor doesn't have to wait for it
+pub fn spawn_log_visit(db: &SurrealDb, shortcut: &RecordId, variant: &RecordId, info: VisitInfo) {
+    let (db, shortcut, variant) = (db.clone(), shortcut.clone(), variant.clone());
+
+    tokio::task::spawn(async move {
+        if let Err(e) = log_visit(&db, &shortcut, &variant, info).await {
+            error!(error = ?e, shortcut = %shortcut, "Failed to log visit");
+        }
+    });
+}
+
+/// Keeps a visit of a burn-after-read shortcut in `archived_visit`, where it outlives the shortcut
+/// and its log, which are deleted right after.
+///
+/// Unlike [`spawn_log_visit`], this waits for the visit to be stored, so it can't race the burn.
+pub async fn archive_visit(
+    db: &SurrealDb,
+    shortcut: &RecordId,
+    variant: &RecordId,
+    info: VisitInfo,
+) {
+    let result = db
+        .query(
+            "CREATE archived_visit SET shortcut = $shortcut, shortlink = $shortcut.shortlink, referrer = $visit.referrer, user_agent = $visit.user_agent, ip = $visit.ip, variant = $visit.variant",
+        )
+        .bind(("shortcut", shortcut.clone()))
+        .bind((
+            "visit",
+            PartialVisit {
+                referrer: info.referrer,
+                user_agent: info.user_agent,
+                ip: info.ip.map(|ip| ip.to_string()),
+                variant: Some(variant.clone()),
+            },
+        ))
+        .await
+        .and_then(|response| response.check());
+
+    if let Err(e) = result {
+        error!(error = ?e, shortcut = %shortcut, "Failed to archive visit");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_network_part_of_ipv4_addresses() {
+        assert_eq!(
+            anonymize_ip("192.168.1.77".parse().unwrap()),
+            "192.168.1.0".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn keeps_the_network_part_of_ipv6_addresses() {
+        assert_eq!(
+            anonymize_ip("2001:db8:abcd:1234:5678::1".parse().unwrap()),
+            "2001:db8:abcd::".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn treats_ipv4_mapped_addresses_as_ipv4() {
+        assert_eq!(
+            anonymize_ip("::ffff:10.1.2.3".parse().unwrap()),
+            "10.1.2.0".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn picks_the_language_with_the_highest_quality() {
+        assert_eq!(
+            preferred_language("de;q=0.5, fr-CH;q=0.8, en;q=0.7").as_deref(),
+            Some("fr-ch")
+        );
+        assert_eq!(
+            preferred_language("en-US,en;q=0.9").as_deref(),
+            Some("en-us")
+        );
+        assert_eq!(preferred_language("nl, de").as_deref(), Some("nl"));
+    }
+
+    #[test]
+    fn ignores_wildcards_and_refused_languages() {
+        assert_eq!(preferred_language("*, de;q=0.1").as_deref(), Some("de"));
+        assert_eq!(preferred_language("en;q=0"), None);
+        assert_eq!(preferred_language(""), None);
+    }
+
+    #[test]
+    fn takes_the_client_from_the_forwarded_for_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(forwarded_for(&headers), None);
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 10.0.0.1"),
+        );
+        assert_eq!(
+            forwarded_for(&headers),
+            Some("203.0.113.7".parse().unwrap())
+        );
+
+        headers.insert("x-forwarded-for", HeaderValue::from_static("unknown"));
+        assert_eq!(forwarded_for(&headers), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Passthrough of the rest of the request to the destination of a shortcut, which turns the
+//! shortcut into an alias of a base URL: `/wiki/Some_Page?action=edit` can lead to
+//! `https://wiki.example.com/Some_Page?action=edit`.
+
+use color_eyre::{eyre::eyre, Result};
+use url::{form_urlencoded, Url};
+
+/// The parts of a request to a shortcut that come after the shortlink
+#[derive(Debug, Default, Clone)]
+pub struct Passthrough {
+    /// Path segments after the shortlink, without the leading slash
+    pub path: Option<String>,
+
+    /// The raw query string
+    pub query: Option<String>,
+}
+
+impl Passthrough {
+    /// Appends the path to the one of `destination` and merges the query parameters into its
+    /// own. Parameters of the request replace parameters of the destination with the same name.
+    pub fn apply(&self, destination: &str) -> Result<String> {
+        let mut url = Url::parse(destination)?;
+
+        if let Some(path) = self.path.as_deref().filter(|path| !path.is_empty()) {
+            url.path_segments_mut()
+                .map_err(|_| eyre!("The destination can't have a path"))?
+                .pop_if_empty()
+                .extend(path.split('/'));
+        }
+
+        if let Some(query) = self.query.as_deref().filter(|query| !query.is_empty()) {
+            let added: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect();
+
+            let kept: Vec<(String, String)> = url
+                .query_pairs()
+                .into_owned()
+                .filter(|(name, _)| !added.iter().any(|(added, _)| added == name))
+                .collect();
+
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(kept)
+                .extend_pairs(added);
+        }
+
+        Ok(url.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: Option<&str>, query: Option<&str>) -> Passthrough {
+        Passthrough {
+            path: path.map(str::to_string),
+            query: query.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn appends_the_path() {
+        assert_eq!(
+            request(Some("Some_Page"), None)
+                .apply("https://wiki.example.com/")
+                .unwrap(),
+            "https://wiki.example.com/Some_Page"
+        );
+        assert_eq!(
+            request(Some("a/b"), None)
+                .apply("https://example.com/docs/")
+                .unwrap(),
+            "https://example.com/docs/a/b"
+        );
+        assert_eq!(
+            request(Some("a"), None)
+                .apply("https://example.com/docs")
+                .unwrap(),
+            "https://example.com/docs/a"
+        );
+    }
+
+    #[test]
+    fn request_parameters_replace_the_destinations() {
+        assert_eq!(
+            request(None, Some("lang=de&page=2"))
+                .apply("https://example.com/?lang=en&x=1")
+                .unwrap(),
+            "https://example.com/?x=1&lang=de&page=2"
+        );
+    }
+
+    #[test]
+    fn leaves_the_destination_alone_without_a_path_or_query() {
+        assert_eq!(
+            request(Some(""), Some(""))
+                .apply("https://example.com/a?b=c")
+                .unwrap(),
+            "https://example.com/a?b=c"
+        );
+    }
+
+    #[test]
+    fn fails_for_destinations_without_a_path() {
+        assert!(request(Some("a"), None)
+            .apply("mailto:someone@example.com")
+            .is_err());
+    }
+}
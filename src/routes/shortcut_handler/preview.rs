@@ -0,0 +1,338 @@
+//! Preview pages, which show where a shortcut leads without following it (or counting a visit).
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use color_eyre::Result;
+use maud::{html, Markup};
+use serde::Deserialize;
+use surrealdb::RecordId;
+use tower_sessions::Session;
+use utoipa_axum::routes;
+
+use crate::{
+    axum_error::AxumResult,
+    routes::{dash::page, Route, RouteType},
+    schema::{File, Paste, Platform, Rotation, RuleConditions},
+    state::SurrealDb,
+};
+
+use super::{find_target, rotation::Variant, unlock, Target};
+
+const PATH: &str = "/preview/{shortlink}";
+
+pub fn routes() -> Vec<Route> {
+    vec![(RouteType::OpenApi(routes!(get_shortcut_preview)), false)]
+}
+
+#[derive(Deserialize)]
+struct Creation {
+    /// When the shortcut was created, formatted for humans
+    created_at: String,
+
+    /// The display name of the user who created the shortcut
+    owner: Option<String>,
+}
+
+/// Shows where a shortcut leads, who created it and when, with a button to follow it
+///
+/// Shortcuts with several destinations list all of them with their weights, and redirect rules are listed with their conditions.
+/// The destination of password-protected shortcuts is only shown once they've been unlocked.
+#[utoipa::path(
+    method(get),
+    path = PATH,
+    params(
+        ("shortlink" = String, Path, description = "The short link to preview")
+    ),
+    responses(
+        (status = OK, description = "Success", body = str, content_type = "text/html")
+    )
+)]
+async fn get_shortcut_preview(
+    State(db): State<SurrealDb>,
+    session: Session,
+    Path(shortlink): Path<String>,
+) -> AxumResult<Response> {
+    let Some(target) = find_target(&db, shortlink.clone()).await? else {
+        return Ok((StatusCode::NOT_FOUND, "Shortcut not found").into_response());
+    };
+
+    let creation: Option<Creation> = db
+        .query("SELECT time::format(timestamp, '%Y-%m-%d %H:%M UTC') AS created_at, in.name AS owner FROM ONLY $shortcut<-created LIMIT 1")
+        .bind(("shortcut", target.id.clone()))
+        .await?
+        .take(0)?;
+
+    let locked = target.password.is_some() && !unlock::is_unlocked(&session, &target.id).await?;
+
+    let destination = match target.variants.as_slice() {
+        _ if locked => html! { "Hidden until the password is entered" },
+        [] => html! { "Nothing" },
+        [variant] => describe_or_deleted(&db, &variant.object).await?,
+        variants => describe_variants(&db, variants, target.rotation).await?,
+    };
+
+    // rules can lead elsewhere, so they're hidden along with the destination
+    let mut rules = Vec::new();
+    if !locked {
+        for rule in &target.rules {
+            rules.push((
+                describe_conditions(&rule.conditions),
+                describe_or_deleted(&db, &rule.link).await?,
+            ));
+        }
+    }
+
+    Ok(page(
+        html! {
+            h1 { "Where does /" (shortlink) " lead?" }
+
+            table {
+                tbody {
+                    tr {
+                        th { @if target.variants.len() > 1 { "Destinations" } @else { "Destination" } }
+                        td { (destination) }
+                    }
+                    @if !rules.is_empty() {
+                        tr {
+                            th { "Redirect rules" }
+                            td {
+                                "Visitors meeting the conditions of a rule are sent to its link instead. The first matching rule is used."
+                                ol {
+                                    @for (conditions, link) in rules {
+                                        li { (conditions) ": " (link) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    @if let Some(creation) = &creation {
+                        tr {
+                            th { "Created" }
+                            td { (creation.created_at) }
+                        }
+                        tr {
+                            th { "Created by" }
+                            td { (creation.owner.as_deref().unwrap_or("Unknown")) }
+                        }
+                    }
+                    @if let Some(status) = status(&target) {
+                        tr {
+                            th { "Status" }
+                            td { (status) }
+                        }
+                    }
+                }
+            }
+
+            form method="get" action=(format!("/{shortlink}")) {
+                button type="submit" { "Continue" }
+            }
+        },
+        Some("Preview"),
+    )
+    .into_response())
+}
+
+/// Describes the object a shortcut expands to, or returns `None` if it doesn't exist anymore
+async fn describe_destination(db: &SurrealDb, object: &RecordId) -> Result<Option<Markup>> {
+    Ok(match object.table() {
+        "link" => db
+            .query("SELECT VALUE url FROM ONLY $link")
+            .bind(("link", object.clone()))
+            .await?
+            .take::<Option<String>>(0)?
+            .map(|url| html! { pre { (url) } }),
+        "file" => db.select::<Option<File>>(object.clone()).await?.map(|file| {
+            html! {
+                "File " strong { (file.name) } " (" (file.content_type) ", " (file.size) " bytes)"
+            }
+        }),
+        "paste" => db
+            .select::<Option<Paste>>(object.clone())
+            .await?
+            .map(|paste| match paste.title {
+                Some(title) => html! { "Paste " strong { (title) } },
+                None => html! { "Untitled paste" },
+            }),
+        _ => None,
+    })
+}
+
+async fn describe_or_deleted(db: &SurrealDb, object: &RecordId) -> Result<Markup> {
+    Ok(describe_destination(db, object)
+        .await?
+        .unwrap_or_else(|| html! { "Nothing, the destination has been deleted" }))
+}
+
+/// Describes all destinations of a shortcut with several, along with how likely each is to be picked
+async fn describe_variants(
+    db: &SurrealDb,
+    variants: &[Variant],
+    rotation: Rotation,
+) -> Result<Markup> {
+    let total: u64 = variants
+        .iter()
+        .map(|variant| u64::from(variant.weight))
+        .sum();
+
+    let mut descriptions = Vec::with_capacity(variants.len());
+    for variant in variants {
+        descriptions.push((
+            describe_or_deleted(db, &variant.object).await?,
+            variant.weight,
+        ));
+    }
+
+    Ok(html! {
+        @match rotation {
+            Rotation::Random => "Picked at random, by weight:",
+            Rotation::RoundRobin => "Picked in turns, each as many times in a row as its weight:",
+        }
+        ul {
+            @for (description, weight) in descriptions {
+                li {
+                    (description) " (weight " (weight)
+                    @if total > 0 {
+                        ", " (u64::from(weight) * 100 / total) "%"
+                    }
+                    ")"
+                }
+            }
+        }
+    })
+}
+
+/// Describes the conditions of a redirect rule for humans
+fn describe_conditions(conditions: &RuleConditions) -> String {
+    let mut parts = Vec::new();
+
+    if !conditions.platforms.is_empty() {
+        let platforms: Vec<_> = conditions
+            .platforms
+            .iter()
+            .map(|platform| match platform {
+                Platform::Ios => "iOS",
+                Platform::Android => "Android",
+                Platform::Desktop => "desktop",
+            })
+            .collect();
+        parts.push(format!("on {}", platforms.join(" or ")));
+    }
+
+    if !conditions.languages.is_empty() {
+        parts.push(format!("preferring {}", conditions.languages.join(" or ")));
+    }
+
+    if !conditions.referrers.is_empty() {
+        parts.push(format!("coming from {}", conditions.referrers.join(" or ")));
+    }
+
+    if !conditions.weekdays.is_empty() {
+        let weekdays: Vec<_> = conditions
+            .weekdays
+            .iter()
+            .map(|&weekday| match weekday {
+                1 => "Monday".to_string(),
+                2 => "Tuesday".to_string(),
+                3 => "Wednesday".to_string(),
+                4 => "Thursday".to_string(),
+                5 => "Friday".to_string(),
+                6 => "Saturday".to_string(),
+                7 => "Sunday".to_string(),
+                weekday => format!("weekday {weekday}"),
+            })
+            .collect();
+        parts.push(format!("on {}", weekdays.join(" or ")));
+    }
+
+    match (conditions.from_hour, conditions.to_hour) {
+        (Some(from), Some(to)) => parts.push(format!("between {from}:00 and {to}:00")),
+        (Some(from), None) => parts.push(format!("from {from}:00")),
+        (None, Some(to)) => parts.push(format!("before {to}:00")),
+        (None, None) => {}
+    }
+
+    if parts.is_empty() {
+        return "Everyone".to_string();
+    }
+
+    let mut description = format!("Visitors {}", parts.join(", "));
+
+    // the offset only matters for the weekdays and hours
+    if !conditions.weekdays.is_empty()
+        || conditions.from_hour.is_some()
+        || conditions.to_hour.is_some()
+    {
+        let offset = conditions.utc_offset;
+        if offset == 0 {
+            description.push_str(" (UTC)");
+        } else {
+            let sign = if offset < 0 { '-' } else { '+' };
+            description.push_str(&format!(
+                " (UTC{sign}{:02}:{:02})",
+                offset.abs() / 60,
+                offset.abs() % 60
+            ));
+        }
+    }
+
+    description
+}
+
+/// Explains why following the shortcut wouldn't work right now
+fn status(target: &Target) -> Option<String> {
+    if target.expired {
+        Some("Expired".to_string())
+    } else if target.pending {
+        Some(match &target.active_from {
+            Some(active_from) => format!("Not active until {active_from}"),
+            None => "Not active yet".to_string(),
+        })
+    } else if target.exhausted() {
+        Some("Visit limit reached".to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_rules_without_conditions() {
+        assert_eq!(describe_conditions(&RuleConditions::default()), "Everyone");
+    }
+
+    #[test]
+    fn describes_all_conditions() {
+        let conditions = RuleConditions {
+            platforms: vec![Platform::Ios, Platform::Android],
+            languages: vec!["de".to_string()],
+            weekdays: vec![6, 7],
+            from_hour: Some(22),
+            to_hour: Some(6),
+            utc_offset: -330,
+            referrers: vec!["example.com".to_string()],
+        };
+
+        assert_eq!(
+            describe_conditions(&conditions),
+            "Visitors on iOS or Android, preferring de, coming from example.com, on Saturday or Sunday, between 22:00 and 6:00 (UTC-05:30)"
+        );
+    }
+
+    #[test]
+    fn leaves_out_the_offset_without_times() {
+        let conditions = RuleConditions {
+            languages: vec!["fr".to_string()],
+            utc_offset: 60,
+            ..Default::default()
+        };
+
+        assert_eq!(describe_conditions(&conditions), "Visitors preferring fr");
+    }
+}
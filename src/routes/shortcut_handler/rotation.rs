@@ -0,0 +1,105 @@
+//! Shortcuts with several destinations, which are picked by weight, either at random or in turns.
+//! That makes it possible to spread visitors over mirrors or to A/B test landing pages.
+
+use serde::Deserialize;
+use surrealdb::RecordId;
+
+use crate::schema::{default_weight, Rotation};
+
+#[derive(Deserialize)]
+pub struct Variant {
+    pub object: RecordId,
+
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// Picks the destination the visitor is sent to, given how many times the shortcut has been visited.
+///
+/// Round-robin goes by the visit count, so concurrent visits can occasionally get the same
+/// destination. If all weights are 0, the first destination is used.
+pub fn pick(variants: &[Variant], rotation: Rotation, visits: u64) -> Option<&RecordId> {
+    let total: u64 = variants
+        .iter()
+        .map(|variant| u64::from(variant.weight))
+        .sum();
+
+    if total == 0 {
+        return variants.first().map(|variant| &variant.object);
+    }
+
+    let mut point = match rotation {
+        Rotation::Random => rand::random_range(0..total),
+        Rotation::RoundRobin => visits % total,
+    };
+
+    for variant in variants {
+        let weight = u64::from(variant.weight);
+        if point < weight {
+            return Some(&variant.object);
+        }
+        point -= weight;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants(weights: &[u32]) -> Vec<Variant> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| Variant {
+                object: RecordId::from_table_key("link", i.to_string()),
+                weight,
+            })
+            .collect()
+    }
+
+    fn picked(variants: &[Variant], rotation: Rotation, visits: u64) -> Option<String> {
+        pick(variants, rotation, visits).map(|object| object.key().to_string())
+    }
+
+    #[test]
+    fn round_robin_visits_each_destination_as_often_as_its_weight() {
+        let variants = variants(&[2, 1]);
+
+        let picks: Vec<_> = (0..6)
+            .map(|visits| picked(&variants, Rotation::RoundRobin, visits).unwrap())
+            .collect();
+
+        assert_eq!(picks, ["0", "0", "1", "0", "0", "1"]);
+    }
+
+    #[test]
+    fn destinations_without_weight_are_skipped() {
+        let variants = variants(&[0, 1, 0]);
+
+        for visits in 0..3 {
+            assert_eq!(
+                picked(&variants, Rotation::RoundRobin, visits).as_deref(),
+                Some("1")
+            );
+            assert_eq!(
+                picked(&variants, Rotation::Random, visits).as_deref(),
+                Some("1")
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_first_destination_if_all_weights_are_zero() {
+        assert_eq!(
+            picked(&variants(&[0, 0]), Rotation::Random, 5).as_deref(),
+            Some("0")
+        );
+    }
+
+    #[test]
+    fn picks_nothing_without_destinations() {
+        assert_eq!(picked(&[], Rotation::RoundRobin, 0), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Conditional redirects: rules that send some visitors of a shortcut to another link, depending
+//! on their platform, language, where they came from or when they visit.
+
+use chrono::{DateTime, Datelike as _, FixedOffset, Timelike as _, Utc};
+use surrealdb::RecordId;
+
+use crate::schema::{Platform, RedirectRule, RuleConditions};
+
+use super::analytics::VisitInfo;
+
+/// Works out the platform of a visitor from their user agent
+fn platform(user_agent: &str) -> Platform {
+    if ["iPhone", "iPad", "iPod"]
+        .iter()
+        .any(|device| user_agent.contains(device))
+    {
+        Platform::Ios
+    } else if user_agent.contains("Android") {
+        Platform::Android
+    } else {
+        Platform::Desktop
+    }
+}
+
+fn language_matches(wanted: &str, language: &str) -> bool {
+    language == wanted
+        || language
+            .strip_prefix(wanted)
+            .is_some_and(|region| region.starts_with('-'))
+}
+
+fn host_matches(wanted: &str, host: &str) -> bool {
+    host == wanted
+        || host
+            .strip_suffix(wanted)
+            .is_some_and(|subdomain| subdomain.ends_with('.'))
+}
+
+fn hour_matches(from: Option<u8>, to: Option<u8>, hour: u8) -> bool {
+    match (from, to) {
+        (Some(from), Some(to)) if from <= to => (from..to).contains(&hour),
+        // wraps around midnight
+        (Some(from), Some(to)) => hour >= from || hour < to,
+        (Some(from), None) => hour >= from,
+        (None, Some(to)) => hour < to,
+        (None, None) => true,
+    }
+}
+
+/// Whether a visit at `now` meets all conditions of a rule
+fn conditions_met(conditions: &RuleConditions, visit: &VisitInfo, now: DateTime<Utc>) -> bool {
+    if !conditions.platforms.is_empty()
+        && !visit
+            .user_agent
+            .as_deref()
+            .is_some_and(|user_agent| conditions.platforms.contains(&platform(user_agent)))
+    {
+        return false;
+    }
+
+    if !conditions.languages.is_empty()
+        && !visit.language.as_deref().is_some_and(|language| {
+            conditions
+                .languages
+                .iter()
+                .any(|wanted| language_matches(wanted, language))
+        })
+    {
+        return false;
+    }
+
+    if !conditions.referrers.is_empty()
+        && !visit.referrer.as_deref().is_some_and(|host| {
+            conditions
+                .referrers
+                .iter()
+                .any(|wanted| host_matches(wanted, host))
+        })
+    {
+        return false;
+    }
+
+    let Some(offset) = FixedOffset::east_opt(conditions.utc_offset * 60) else {
+        return false;
+    };
+    let now = now.with_timezone(&offset);
+
+    let weekday = now.weekday().number_from_monday() as u8;
+    if !conditions.weekdays.is_empty() && !conditions.weekdays.contains(&weekday) {
+        return false;
+    }
+
+    hour_matches(conditions.from_hour, conditions.to_hour, now.hour() as u8)
+}
+
+/// Returns the link of the first rule the visitor meets the conditions of
+pub fn matching_link<'a>(rules: &'a [RedirectRule], visit: &VisitInfo) -> Option<&'a RecordId> {
+    let now = Utc::now();
+
+    rules
+        .iter()
+        .find(|rule| conditions_met(&rule.conditions, visit, now))
+        .map(|rule| &rule.link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visit() -> VisitInfo {
+        VisitInfo {
+            referrer: None,
+            user_agent: None,
+            ip: None,
+            language: None,
+        }
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn detects_the_platform() {
+        assert_eq!(
+            platform("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X)"),
+            Platform::Ios
+        );
+        assert_eq!(
+            platform("Mozilla/5.0 (Linux; Android 14; Pixel 8)"),
+            Platform::Android
+        );
+        assert_eq!(
+            platform("Mozilla/5.0 (X11; Linux x86_64)"),
+            Platform::Desktop
+        );
+    }
+
+    #[test]
+    fn languages_match_their_regional_variants() {
+        assert!(language_matches("de", "de"));
+        assert!(language_matches("de", "de-at"));
+        assert!(!language_matches("de", "deu"));
+        assert!(!language_matches("de-at", "de"));
+    }
+
+    #[test]
+    fn hosts_match_their_subdomains() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("example.com", "www.example.com"));
+        assert!(!host_matches("example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn hours_can_wrap_around_midnight() {
+        assert!(hour_matches(Some(9), Some(17), 9));
+        assert!(!hour_matches(Some(9), Some(17), 17));
+
+        assert!(hour_matches(Some(22), Some(6), 23));
+        assert!(hour_matches(Some(22), Some(6), 3));
+        assert!(!hour_matches(Some(22), Some(6), 6));
+        assert!(!hour_matches(Some(22), Some(6), 12));
+
+        assert!(hour_matches(Some(22), None, 22));
+        assert!(!hour_matches(None, Some(6), 6));
+        assert!(hour_matches(None, None, 12));
+    }
+
+    #[test]
+    fn hours_and_weekdays_are_in_the_utc_offset() {
+        let office_hours = RuleConditions {
+            from_hour: Some(9),
+            to_hour: Some(17),
+            utc_offset: 120,
+            ..Default::default()
+        };
+
+        // 08:00 UTC is 10:00 at UTC+2
+        assert!(conditions_met(
+            &office_hours,
+            &visit(),
+            at("2024-01-01T08:00:00Z")
+        ));
+        assert!(!conditions_met(
+            &office_hours,
+            &visit(),
+            at("2024-01-01T15:00:00Z")
+        ));
+
+        let mondays = RuleConditions {
+            weekdays: vec![1],
+            utc_offset: 60,
+            ..Default::default()
+        };
+
+        // late on Sunday in UTC is already Monday at UTC+1
+        assert!(conditions_met(
+            &mondays,
+            &visit(),
+            at("2024-01-07T23:30:00Z")
+        ));
+        assert!(!conditions_met(
+            &mondays,
+            &visit(),
+            at("2024-01-07T22:30:00Z")
+        ));
+    }
+
+    #[test]
+    fn invalid_utc_offsets_never_match() {
+        let conditions = RuleConditions {
+            utc_offset: 24 * 60,
+            ..Default::default()
+        };
+
+        assert!(!conditions_met(
+            &conditions,
+            &visit(),
+            at("2024-01-01T00:00:00Z")
+        ));
+    }
+
+    #[test]
+    fn visitors_without_the_information_dont_match() {
+        let conditions = RuleConditions {
+            platforms: vec![Platform::Android],
+            ..Default::default()
+        };
+
+        assert!(!conditions_met(
+            &conditions,
+            &visit(),
+            at("2024-01-01T00:00:00Z")
+        ));
+        assert!(conditions_met(
+            &conditions,
+            &VisitInfo {
+                user_agent: Some("Mozilla/5.0 (Linux; Android 14)".to_string()),
+                ..visit()
+            },
+            at("2024-01-01T00:00:00Z")
+        ));
+    }
+}
@@ -0,0 +1,46 @@
+//! Shortcuts that only work during a certain period of time, set by their `not_before` and
+//! `expires_at`.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use maud::{html, Markup};
+
+use crate::routes::dash::page;
+
+use super::Target;
+
+/// Returns an error page if the shortcut isn't active right now
+pub fn inactive_response(target: &Target) -> Option<Response> {
+    if target.expired {
+        return Some((StatusCode::GONE, "This link has expired").into_response());
+    }
+
+    if target.pending {
+        return Some(
+            (
+                StatusCode::FORBIDDEN,
+                render_not_yet_active(target.active_from.as_deref()),
+            )
+                .into_response(),
+        );
+    }
+
+    None
+}
+
+fn render_not_yet_active(active_from: Option<&str>) -> Markup {
+    page(
+        html! {
+            h1 { "This link isn't active yet" }
+
+            @if let Some(active_from) = active_from {
+                p { "It will start working on " (active_from) "." }
+            } @else {
+                p { "Please come back later." }
+            }
+        },
+        Some("Not active yet"),
+    )
+}
@@ -0,0 +1,194 @@
+//! Go-link templates: link URLs with placeholders that are filled in from the request, like
+//! `https://jira.example.com/browse/{1}`.
+//!
+//! Numbered placeholders take the path segments after the shortlink, so `/jira/ABC-123` fills
+//! `{1}` with `ABC-123`. Named placeholders take the query parameter with the same name, so
+//! `/search?query=rust` fills `{query}` with `rust`.
+
+use std::ops::Range;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use url::form_urlencoded;
+
+use super::passthrough::Passthrough;
+
+/// Everything except the unreserved characters of RFC 3986, so that values can't change the
+/// structure of the URL they're put into
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Finds the placeholders in a URL, along with the byte ranges they take up (braces included)
+fn placeholders(template: &str) -> Vec<(Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut position = 0;
+
+    while let Some(start) = template[position..].find('{').map(|i| position + i) {
+        let Some(end) = template[start..].find('}').map(|i| start + i) else {
+            break;
+        };
+
+        let name = &template[start + 1..end];
+        if is_placeholder_name(name) {
+            found.push((start..end + 1, name));
+            position = end + 1;
+        } else {
+            position = start + 1;
+        }
+    }
+
+    found
+}
+
+pub fn is_template(url: &str) -> bool {
+    !placeholders(url).is_empty()
+}
+
+/// Fills in the placeholders of `template` from the request.
+///
+/// Returns the name of the first placeholder there's no value for if any are missing.
+pub fn fill(template: &str, request: &Passthrough) -> Result<String, String> {
+    let segments: Vec<&str> = request
+        .path
+        .as_deref()
+        .map(|path| {
+            path.split('/')
+                .filter(|segment| !segment.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let parameters: Vec<(String, String)> = request
+        .query
+        .as_deref()
+        .map(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut filled = String::with_capacity(template.len());
+    let mut position = 0;
+
+    for (range, name) in placeholders(template) {
+        let value = match name.parse::<usize>() {
+            Ok(index) => index
+                .checked_sub(1)
+                .and_then(|index| segments.get(index))
+                .map(|segment| segment.to_string()),
+            Err(_) => parameters
+                .iter()
+                .find(|(parameter, _)| parameter == name)
+                .map(|(_, value)| value.clone()),
+        }
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| name.to_string())?;
+
+        filled.push_str(&template[position..range.start]);
+        filled.extend(utf8_percent_encode(&value, COMPONENT));
+        position = range.end;
+    }
+
+    filled.push_str(&template[position..]);
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: Option<&str>, query: Option<&str>) -> Passthrough {
+        Passthrough {
+            path: path.map(str::to_string),
+            query: query.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn finds_only_valid_placeholders() {
+        assert!(is_template("https://jira.example.com/browse/{1}"));
+        assert!(is_template("https://example.com/search?q={query}"));
+        assert!(!is_template("https://example.com/{}"));
+        assert!(!is_template("https://example.com/{a b}"));
+        assert!(!is_template("https://example.com/{unclosed"));
+    }
+
+    #[test]
+    fn fills_numbered_placeholders_from_path_segments() {
+        assert_eq!(
+            fill(
+                "https://example.com/{2}/{1}",
+                &request(Some("first/second/"), None)
+            ),
+            Ok("https://example.com/second/first".to_string())
+        );
+    }
+
+    #[test]
+    fn fills_named_placeholders_from_query_parameters() {
+        assert_eq!(
+            fill(
+                "https://example.com/search?q={query}",
+                &request(None, Some("other=1&query=rust"))
+            ),
+            Ok("https://example.com/search?q=rust".to_string())
+        );
+    }
+
+    #[test]
+    fn encodes_values_so_they_cant_change_the_url() {
+        assert_eq!(
+            fill(
+                "https://example.com/search?q={query}",
+                &request(None, Some("query=a+b%2Fc%26d%3Fe"))
+            ),
+            Ok("https://example.com/search?q=a%20b%2Fc%26d%3Fe".to_string())
+        );
+
+        assert_eq!(
+            fill("https://example.com/{1}", &request(Some("..%2F~x"), None)),
+            Ok("https://example.com/..%252F~x".to_string())
+        );
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        assert_eq!(
+            fill(
+                "https://example.com/{}/{1}/{a b}",
+                &request(Some("x"), None)
+            ),
+            Ok("https://example.com/{}/x/{a b}".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_the_first_missing_placeholder() {
+        assert_eq!(
+            fill("https://example.com/{1}/{2}", &request(Some("x"), None)),
+            Err("2".to_string())
+        );
+        assert_eq!(
+            fill("https://example.com/{0}", &request(Some("x"), None)),
+            Err("0".to_string())
+        );
+        assert_eq!(
+            fill(
+                "https://example.com/{query}",
+                &request(None, Some("query="))
+            ),
+            Err("query".to_string())
+        );
+    }
+}
@@ -0,0 +1,50 @@
+//! Password protection of shortcuts. Once the right password has been entered, the shortcut stays
+//! unlocked for the rest of the session.
+
+use color_eyre::Result;
+use maud::{html, Markup};
+use surrealdb::RecordId;
+use tower_sessions::Session;
+
+use crate::routes::dash::page;
+
+const UNLOCKED_KEY: &str = "unlocked_shortcuts";
+
+async fn unlocked_shortcuts(session: &Session) -> Result<Vec<String>> {
+    Ok(session
+        .get::<Vec<String>>(UNLOCKED_KEY)
+        .await?
+        .unwrap_or_default())
+}
+
+pub async fn is_unlocked(session: &Session, shortcut: &RecordId) -> Result<bool> {
+    Ok(unlocked_shortcuts(session)
+        .await?
+        .contains(&shortcut.to_string()))
+}
+
+pub async fn unlock(session: &Session, shortcut: &RecordId) -> Result<()> {
+    let mut unlocked = unlocked_shortcuts(session).await?;
+    unlocked.push(shortcut.to_string());
+    session.insert(UNLOCKED_KEY, unlocked).await?;
+
+    Ok(())
+}
+
+pub fn render_unlock_form(shortlink: &str, wrong_password: bool) -> Markup {
+    page(
+        html! {
+            h1 { "This link is password-protected" }
+
+            form method="post" action=(format!("/{shortlink}")) {
+                input type="password" name="password" placeholder="Password" required autofocus;
+                button type="submit" { "Unlock" }
+            }
+
+            @if wrong_password {
+                p class="error" { "Wrong password, please try again." }
+            }
+        },
+        Some("Password required"),
+    )
+}
@@ -0,0 +1,68 @@
+//! Shortcuts that only work a limited number of times, including burn-after-read ones, which
+//! delete the object they point to after the first visit.
+
+use axum::{body::Body, response::Response};
+use color_eyre::Result;
+use futures::StreamExt as _;
+use surrealdb::RecordId;
+use tracing::error;
+
+use crate::{routes::api::file::delete_file, state::SurrealDb, storage::Storage};
+
+/// Counts a visit of the shortcut, unless it has run out of visits.
+///
+/// The check and the increment happen in a single statement, so two concurrent visits can't both
+/// use up the last one.
+pub async fn record_visit(db: &SurrealDb, shortcut: &RecordId) -> Result<bool> {
+    Ok(db
+        .query(
+            "UPDATE ONLY $shortcut SET visits += 1 WHERE (max_visits = NONE OR visits < max_visits) AND (!burn_after_read OR visits = 0) RETURN VALUE id",
+        )
+        .bind(("shortcut", shortcut.clone()))
+        .await?
+        .take::<Option<RecordId>>(0)?
+        .is_some())
+}
+
+/// Deletes a file once the response streaming it has been sent or abandoned
+struct BurnFileOnDrop {
+    db: SurrealDb,
+    storage: Storage,
+    id: RecordId,
+}
+
+impl Drop for BurnFileOnDrop {
+    fn drop(&mut self) {
+        let (db, storage, id) = (self.db.clone(), self.storage.clone(), self.id.clone());
+
+        tokio::task::spawn(async move {
+            if let Err(e) = delete_file(&db, &storage, &id).await {
+                error!(error = ?e, file = %id, "Failed to delete burned file");
+            }
+        });
+    }
+}
+
+/// Makes a response streaming a file delete the file after it's done.
+///
+/// The file can't be deleted right away, as its content is still being read from the storage.
+pub fn burn_file_after(
+    db: &SurrealDb,
+    storage: &Storage,
+    id: RecordId,
+    response: Response,
+) -> Response {
+    let guard = BurnFileOnDrop {
+        db: db.clone(),
+        storage: storage.clone(),
+        id,
+    };
+
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            // keeps the file around until the stream is dropped
+            let _guard = &guard;
+            chunk
+        }))
+    })
+}
@@ -8,7 +8,11 @@ use surrealdb::{engine::any::Any, Datetime, RecordId, RecordIdKey, Surreal};
 use utoipa::ToSchema;
 use visible::StructFields;
 
-use crate::{state::SurrealDb, GroupClaims};
+use crate::{
+    settings::{ProfileClaim, ProfileClaims, RedirectStatus},
+    state::SurrealDb,
+    GroupClaims,
+};
 
 // TODO: make the objects implement `ToSchema` so that I don't have to create another struct for the OpenAPI documentation
 // that would require dealing with `RecordId`
@@ -28,19 +32,226 @@ database_object!(User {
     id: RecordId,
     subject: String,
     name: String,
-    email: String,
+    email: Option<String>,
+
+    /// The OIDC groups the user was a member of when they last logged in
+    #[serde(default)]
+    groups: Vec<String>,
+
+    last_login: Option<Datetime>,
+});
+
+database_object!(Group {
+    id: RecordId,
+    name: String,
+});
+
+database_object!(Token {
+    id: RecordId,
+    name: String,
+    hash: String,
+
+    /// What the token can be used for, like `link:read`, or `*` for everything
+    #[serde(default)]
+    scopes: Vec<String>,
+
+    expires_at: Option<Datetime>,
 });
 
 database_object!(Link {
     id: RecordId,
+
+    /// Where the link leads. Can be a go-link template with placeholders like `{1}` or `{query}`.
     url: String,
+
+    /// Where a template leads when some of its placeholders don't get a value
+    fallback_url: Option<String>,
+
+    title: Option<String>,
+    description: Option<String>,
+
+    /// Free-form notes of the owner
+    notes: Option<String>,
+
+    /// Lowercase tags to find the link by
+    #[serde(default)]
+    tags: Vec<String>,
+});
+
+database_object!(File {
+    id: RecordId,
+    name: String,
+    content_type: String,
+    size: u64,
+    expires_at: Option<Datetime>,
+
+    width: Option<u32>,
+    height: Option<u32>,
+
+    /// Sizes of the thumbnails generated for this file
+    #[serde(default)]
+    thumbnails: Vec<u32>,
+});
+
+database_object!(Paste {
+    id: RecordId,
+    title: Option<String>,
+    language: Option<String>,
+    content: String,
+});
+
+database_object!(Upload {
+    id: RecordId,
+    name: String,
+    content_type: String,
+    length: u64,
+    received: u64,
+    shortcuts: Vec<String>,
+    expires_at: Option<Datetime>,
+
+    #[serde(default)]
+    keep_metadata: bool,
+
+    /// Settings of the shortcuts created when the upload is finished
+    #[serde(default)]
+    shortcut_options: ShortcutOptions,
 });
 
 database_object!(Shortcut {
     id: RecordId,
     shortlink: String,
+
+    /// Argon2 hash of the password needed to follow the shortcut
+    password: Option<String>,
+
+    /// After how many visits the shortcut stops working
+    max_visits: Option<u64>,
+
+    #[serde(default)]
+    visits: u64,
+
+    /// Whether the object the shortcut expands to should be deleted after the first visit
+    #[serde(default)]
+    burn_after_read: bool,
+
+    not_before: Option<Datetime>,
+    expires_at: Option<Datetime>,
+
+    /// The status code links are redirected with, if it differs from the instance default
+    redirect_status: Option<RedirectStatus>,
+
+    /// Whether extra path segments and query parameters are passed through to the destination link
+    #[serde(default)]
+    passthrough: bool,
+
+    /// How the destination is picked when the shortcut expands to several links
+    #[serde(default)]
+    rotation: Rotation,
+
+    /// Rules sending visitors to other links, checked in order before picking a destination
+    #[serde(default)]
+    rules: Vec<RedirectRule>,
 });
 
+/// How shortcuts with several destinations pick the one a visitor is sent to
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    /// Pick a random destination, with chances proportional to the weights
+    #[default]
+    Random,
+
+    /// Go through the destinations in order, visiting each as many times as its weight
+    RoundRobin,
+}
+
+/// Sends the visitors of a shortcut who meet the conditions to another link
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RedirectRule {
+    pub link: RecordId,
+
+    #[serde(default)]
+    pub conditions: RuleConditions,
+}
+
+/// Conditions of a redirect rule. A rule applies when all of its conditions are met, and conditions
+/// that aren't set are always met.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, ToSchema)]
+pub struct RuleConditions {
+    /// Platforms, one of which the visitor's user agent has to be on
+    #[serde(default)]
+    pub platforms: Vec<Platform>,
+
+    /// Language tags like `de` or `pt-br`, one of which has to be the visitor's preferred language.
+    /// `de` also matches regional variants like `de-at`.
+    #[serde(default)]
+    pub languages: Vec<String>,
+
+    /// ISO weekdays the visit has to happen on, from 1 (Monday) to 7 (Sunday)
+    #[serde(default)]
+    pub weekdays: Vec<u8>,
+
+    /// Hour of the day (0-23) the rule starts applying at
+    pub from_hour: Option<u8>,
+
+    /// Hour of the day (0-23) the rule stops applying at. Can be lower than `from_hour` to wrap around midnight.
+    pub to_hour: Option<u8>,
+
+    /// Offset from UTC that weekdays and hours are in, in minutes
+    #[serde(default)]
+    pub utc_offset: i32,
+
+    /// Hosts, one of which the visitor has to come from. Subdomains match too.
+    #[serde(default)]
+    pub referrers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Ios,
+    Android,
+    Desktop,
+}
+
+/// Settings of newly created shortcuts
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ShortcutOptions {
+    /// Argon2 hash of the password needed to follow the shortcuts
+    pub password: Option<String>,
+
+    pub max_visits: Option<u64>,
+
+    #[serde(default)]
+    pub burn_after_read: bool,
+
+    pub not_before: Option<Datetime>,
+    pub expires_at: Option<Datetime>,
+
+    pub redirect_status: Option<RedirectStatus>,
+
+    #[serde(default)]
+    pub passthrough: bool,
+}
+
+database_object!(
+    Visit {
+        id: RecordId,
+        timestamp: Datetime,
+
+        /// The host of the page the visitor came from
+        referrer: Option<String>,
+        user_agent: Option<String>,
+
+        /// The anonymized IP address of the visitor
+        ip: Option<String>,
+
+        /// The object the visitor was sent to
+        variant: Option<RecordId>,
+    },
+    timestamp
+);
+
 database_object!(ExpandsTo {
     id: RecordId,
 
@@ -49,6 +260,45 @@ database_object!(ExpandsTo {
 
     #[serde(rename = "out")]
     object: RecordId,
+
+    /// How often this destination is picked compared to the other ones of the shortcut
+    #[serde(default = "default_weight")]
+    weight: u32,
+});
+
+pub fn default_weight() -> u32 {
+    1
+}
+
+database_object!(Logged {
+    id: RecordId,
+
+    #[serde(rename = "in")]
+    shortcut: RecordId,
+
+    #[serde(rename = "out")]
+    visit: RecordId,
+});
+
+database_object!(MemberOf {
+    id: RecordId,
+
+    #[serde(rename = "in")]
+    user: RecordId,
+
+    #[serde(rename = "out")]
+    group: RecordId,
+});
+
+database_object!(Owns {
+    id: RecordId,
+
+    #[serde(rename = "in")]
+    group: RecordId,
+
+    /// The link or shortcut owned by the group
+    #[serde(rename = "out")]
+    object: RecordId,
 });
 
 database_object!(
@@ -66,12 +316,123 @@ database_object!(
     timestamp
 );
 
-impl From<OidcClaims<GroupClaims>> for PartialUser {
-    fn from(claims: OidcClaims<GroupClaims>) -> Self {
+database_object!(
+    Changed {
+        id: RecordId,
+
+        #[serde(rename = "in")]
+        user: RecordId,
+
+        /// The link or shortcut that was changed
+        #[serde(rename = "out")]
+        object: RecordId,
+
+        timestamp: Datetime,
+
+        /// The URL of the link before the change
+        previous_url: Option<String>,
+
+        /// The shortlink of the shortcut before the change
+        previous_shortlink: Option<String>,
+
+        /// What the shortcut expanded to before the change
+        #[serde(default)]
+        previous_objects: Vec<RecordId>,
+    },
+    timestamp
+);
+
+/// Returns the value of a standard claim, if the identity provider sent it
+fn claim_value(claims: &OidcClaims<GroupClaims>, claim: ProfileClaim) -> Option<String> {
+    let value = match claim {
+        ProfileClaim::Name => claims
+            .name()
+            .and_then(|name| name.get(None))
+            .map(|name| name.to_string()),
+        ProfileClaim::PreferredUsername => claims
+            .preferred_username()
+            .map(|username| username.to_string()),
+        ProfileClaim::Nickname => claims
+            .nickname()
+            .and_then(|nickname| nickname.get(None))
+            .map(|nickname| nickname.to_string()),
+        ProfileClaim::GivenName => claims
+            .given_name()
+            .and_then(|name| name.get(None))
+            .map(|name| name.to_string()),
+        ProfileClaim::FamilyName => claims
+            .family_name()
+            .and_then(|name| name.get(None))
+            .map(|name| name.to_string()),
+        ProfileClaim::Email => claims.email().map(|email| email.to_string()),
+        ProfileClaim::Subject => Some(claims.subject().to_string()),
+    };
+
+    value.filter(|value| !value.trim().is_empty())
+}
+
+/// Whether a claim is an email address, by the same rules as `string::is::email` in SurrealDB
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+
+    let local_ok = !local.is_empty()
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c));
+
+    let domain_ok = domain.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    local_ok && domain_ok
+}
+
+impl PartialUser {
+    /// Builds the profile of a user from the claims of their ID token, mapped according to `mapping`
+    pub fn from_claims(claims: &OidcClaims<GroupClaims>, mapping: &ProfileClaims) -> Self {
+        let first_claim = |claims_to_try: &[ProfileClaim]| {
+            claims_to_try
+                .iter()
+                .find_map(|claim| claim_value(claims, *claim))
+        };
+
         Self {
             subject: claims.subject().to_string(),
-            name: claims.name().unwrap().get(None).unwrap().to_string(),
-            email: claims.email().unwrap().to_string(),
+            name: first_claim(&mapping.name).unwrap_or_else(|| claims.subject().to_string()),
+            // any claim can be mapped to the email address, so the ones that aren't one are skipped
+            email: mapping
+                .email
+                .iter()
+                .filter_map(|claim| claim_value(claims, *claim))
+                .find(|value| is_email(value)),
+            groups: claims.additional_claims().groups.clone(),
+            last_login: Some(Datetime::from(chrono::Utc::now())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_email_addresses() {
+        assert!(is_email("someone@example.com"));
+        assert!(is_email("first.last+tag@mail.example.co.uk"));
+        assert!(is_email("admin@localhost"));
+
+        assert!(!is_email("someone"));
+        assert!(!is_email("@example.com"));
+        assert!(!is_email("someone@"));
+        assert!(!is_email("some one@example.com"));
+        assert!(!is_email("someone@example..com"));
+        assert!(!is_email("someone@-example.com"));
+        assert!(!is_email("a@b@example.com"));
+    }
+}
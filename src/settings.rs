@@ -1,11 +1,13 @@
 use std::{
+    collections::HashMap,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
     sync::Arc,
 };
 
 use color_eyre::{eyre::Context as _, Section as _};
 use config::{Config, ConfigError, Environment, File};
-use http::Uri;
+use http::{StatusCode, Uri};
 use openidconnect::{ClientId, ClientSecret, IssuerUrl};
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, Display, EnumString, IntoStaticStr};
@@ -47,6 +49,21 @@ pub struct General {
 
     #[serde(with = "http_serde_ext::uri")]
     pub public_url: Uri,
+
+    #[serde(default)]
+    pub storage: StorageBackend,
+
+    /// Take client addresses from the `X-Forwarded-For` header. Only enable this behind a reverse proxy that sets it.
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    Local,
+    S3,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -65,6 +82,189 @@ pub struct Oidc {
     pub issuer: IssuerUrl,
     pub client_id: ClientId,
     pub client_secret: Option<ClientSecret>,
+
+    /// Members of this OIDC group can use the admin API to manage everyone's objects
+    #[serde(default)]
+    pub admin_group: Option<String>,
+
+    #[serde(default)]
+    pub profile_claims: ProfileClaims,
+}
+
+/// Standard ID token claims user profiles can be filled from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileClaim {
+    Name,
+    PreferredUsername,
+    Nickname,
+    GivenName,
+    FamilyName,
+    Email,
+    Subject,
+}
+
+/// Which claims user profiles are filled from on every login. The first claim the identity
+/// provider sends is used.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProfileClaims {
+    /// Falls back to the subject if none of the claims are sent
+    pub name: Vec<ProfileClaim>,
+
+    /// Users don't have an email address if none of the claims are sent, or none of them is an email address
+    pub email: Vec<ProfileClaim>,
+}
+
+impl Default for ProfileClaims {
+    fn default() -> Self {
+        Self {
+            name: vec![
+                ProfileClaim::Name,
+                ProfileClaim::PreferredUsername,
+                ProfileClaim::Nickname,
+                ProfileClaim::Email,
+            ],
+            email: vec![ProfileClaim::Email],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Local {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct S3 {
+    /// Custom endpoint for S3-compatible services like MinIO
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub bucket: String,
+
+    pub access_key_id: String,
+    pub secret_access_key: String,
+
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+/// Lifetimes of uploaded files, in seconds. Files without a lifetime are kept forever.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Files {
+    /// Used when the uploader doesn't choose a lifetime
+    pub default_lifetime: Option<u64>,
+
+    /// Upper limit for the lifetime chosen by the uploader
+    pub max_lifetime: Option<u64>,
+}
+
+/// Status codes that shortcuts to links can redirect with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectStatus {
+    /// 301, cached by browsers and search engines
+    MovedPermanently,
+    /// 302
+    Found,
+    /// 307
+    #[default]
+    TemporaryRedirect,
+    /// 308, cached by browsers and search engines
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Self::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            Self::Found => StatusCode::FOUND,
+            Self::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            Self::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            301 => Ok(Self::MovedPermanently),
+            302 => Ok(Self::Found),
+            307 => Ok(Self::TemporaryRedirect),
+            308 => Ok(Self::PermanentRedirect),
+            _ => Err(format!(
+                "{value} is not a supported redirect status, use 301, 302, 307 or 308"
+            )),
+        }
+    }
+}
+
+impl From<RedirectStatus> for u16 {
+    fn from(value: RedirectStatus) -> Self {
+        value.status_code().as_u16()
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Links {
+    /// Used for shortcuts that don't choose a redirect status
+    #[serde(default)]
+    pub default_redirect_status: RedirectStatus,
+}
+
+/// Storage limits, in bytes. Limits that aren't set don't apply.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Quota {
+    /// Maximum size of a single upload
+    pub max_upload_size: Option<u64>,
+
+    /// Maximum total size of all files and pending uploads of a user
+    pub max_total_size: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Quotas {
+    #[serde(default)]
+    pub default: Quota,
+
+    /// Overrides for members of OIDC groups, keyed on the group name
+    #[serde(default)]
+    pub groups: HashMap<String, Quota>,
+}
+
+/// Processing of uploaded images
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Images {
+    /// Thumbnails are generated to fit in squares of these sizes, in pixels
+    pub thumbnail_sizes: Vec<u32>,
+
+    /// Images larger than this (in bytes) don't get thumbnails, and are refused unless the uploader
+    /// asks for their metadata to be kept, as it can't be stripped from them
+    pub max_size: u64,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self {
+            thumbnail_sizes: vec![128, 512],
+            max_size: 50 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Tus {
+    /// Directory where partial uploads are kept until they are complete
+    pub staging_path: PathBuf,
+}
+
+impl Default for Tus {
+    fn default() -> Self {
+        Self {
+            staging_path: PathBuf::from("uploads-partial"),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -72,6 +272,23 @@ pub struct Settings {
     pub general: General,
     pub db: Db,
     pub oidc: Oidc,
+    pub local: Option<Local>,
+    pub s3: Option<S3>,
+
+    #[serde(default)]
+    pub files: Files,
+
+    #[serde(default)]
+    pub links: Links,
+
+    #[serde(default)]
+    pub quotas: Quotas,
+
+    #[serde(default)]
+    pub images: Images,
+
+    #[serde(default)]
+    pub tus: Tus,
 }
 
 pub type ArcSettings = Arc<Settings>;
@@ -139,6 +356,8 @@ impl Settings {
                 public_url: "http://localhost:8080"
                     .parse()
                     .expect("hardcoded uri should parse"),
+                storage: StorageBackend::Local,
+                trust_proxy_headers: false,
             },
             db: Db {
                 endpoint: "ws://localhost:8000".to_string(),
@@ -153,7 +372,27 @@ impl Settings {
                 issuer: IssuerUrl::new("https://example.com".to_string()).unwrap(),
                 client_id: ClientId::new("client_id".to_string()),
                 client_secret: Some(ClientSecret::new("client_secret".to_string())),
+                admin_group: None,
+                profile_claims: ProfileClaims::default(),
+            },
+            local: Some(Local {
+                path: PathBuf::from("uploads"),
+            }),
+            s3: None,
+            files: Files {
+                default_lifetime: Some(60 * 60 * 24 * 7),
+                max_lifetime: Some(60 * 60 * 24 * 30),
+            },
+            links: Links::default(),
+            quotas: Quotas {
+                default: Quota {
+                    max_upload_size: Some(100 * 1024 * 1024),
+                    max_total_size: Some(1024 * 1024 * 1024),
+                },
+                groups: HashMap::new(),
             },
+            images: Images::default(),
+            tus: Tus::default(),
         }
     }
 }
@@ -3,7 +3,7 @@ use std::{ops::Deref, sync::Arc};
 use axum::extract::FromRef;
 use surrealdb::{engine::any::Any, Surreal};
 
-use crate::settings::ArcSettings;
+use crate::{settings::ArcSettings, storage::Storage};
 
 #[derive(Clone)]
 pub struct AppState(Arc<InnerState>);
@@ -25,6 +25,7 @@ impl Deref for AppState {
 pub struct InnerState {
     pub settings: ArcSettings,
     pub db: SurrealDb,
+    pub storage: Storage,
 }
 
 impl FromRef<AppState> for ArcSettings {
@@ -40,3 +41,9 @@ impl FromRef<AppState> for SurrealDb {
         state.db.clone()
     }
 }
+
+impl FromRef<AppState> for Storage {
+    fn from_ref(state: &AppState) -> Self {
+        state.storage.clone()
+    }
+}
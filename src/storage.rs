@@ -0,0 +1,60 @@
+mod local;
+mod s3;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use color_eyre::{eyre::OptionExt, Result};
+use futures::{
+    future,
+    stream::{self, BoxStream},
+    StreamExt as _,
+};
+
+use crate::settings::{Settings, StorageBackend};
+
+pub type ByteStream<'a> = BoxStream<'a, std::io::Result<Bytes>>;
+
+/// A place where the content of uploaded objects is kept.
+///
+/// Only the content lives here - all metadata is stored in SurrealDB, and blobs are addressed by
+/// keys derived from record ids, so the same records work with any backend.
+#[async_trait]
+pub trait BlobStorage: Send + Sync {
+    /// Stores the content of `body` under `key`, returning the number of bytes written
+    async fn put(&self, key: &str, body: ByteStream<'_>) -> Result<u64>;
+
+    /// Stores `bytes` under `key`, returning the number of bytes written
+    async fn put_bytes(&self, key: &str, bytes: Bytes) -> Result<u64> {
+        self.put(key, stream::once(future::ready(Ok(bytes))).boxed())
+            .await
+    }
+
+    /// Streams the content stored under `key`
+    async fn get(&self, key: &str) -> Result<ByteStream<'static>>;
+
+    /// Deletes the content stored under `key`
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+pub type Storage = Arc<dyn BlobStorage>;
+
+pub async fn init_storage(settings: &Settings) -> Result<Storage> {
+    Ok(match settings.general.storage {
+        StorageBackend::Local => Arc::new(
+            local::LocalStorage::new(
+                settings
+                    .local
+                    .as_ref()
+                    .ok_or_eyre("the `local` section is required for the local storage backend")?,
+            )
+            .await?,
+        ),
+        StorageBackend::S3 => {
+            Arc::new(s3::S3Storage::new(settings.s3.as_ref().ok_or_eyre(
+                "the `s3` section is required for the S3 storage backend",
+            )?)?)
+        }
+    })
+}
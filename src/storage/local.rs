@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use color_eyre::{eyre::WrapErr as _, Result};
+use futures::{StreamExt as _, TryStreamExt as _};
+use tokio::io::AsyncWriteExt as _;
+use tokio_util::io::ReaderStream;
+
+use crate::settings::Local;
+
+use super::{BlobStorage, ByteStream};
+
+pub struct LocalStorage {
+    path: PathBuf,
+}
+
+impl LocalStorage {
+    pub async fn new(settings: &Local) -> Result<Self> {
+        tokio::fs::create_dir_all(&settings.path)
+            .await
+            .wrap_err("failed to create local storage directory")?;
+
+        Ok(Self {
+            path: settings.path.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStorage for LocalStorage {
+    async fn put(&self, key: &str, mut body: ByteStream<'_>) -> Result<u64> {
+        let path = self.path.join(key);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut blob = tokio::fs::File::create(&path).await?;
+        let mut size = 0;
+
+        while let Some(chunk) = body.try_next().await? {
+            size += chunk.len() as u64;
+            blob.write_all(&chunk).await?;
+        }
+
+        blob.flush().await?;
+
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream<'static>> {
+        let blob = tokio::fs::File::open(self.path.join(key)).await?;
+
+        Ok(ReaderStream::new(blob).boxed())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        Ok(tokio::fs::remove_file(self.path.join(key)).await?)
+    }
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use color_eyre::Result;
+use futures::{StreamExt as _, TryStreamExt as _};
+use object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path,
+    ObjectStore as _, WriteMultipart,
+};
+
+use crate::settings::S3;
+
+use super::{BlobStorage, ByteStream};
+
+/// How many parts can be uploaded concurrently before `put` waits for one of them to finish
+const MAX_CONCURRENT_PARTS: usize = 8;
+
+pub struct S3Storage {
+    store: AmazonS3,
+}
+
+impl S3Storage {
+    pub fn new(settings: &S3) -> Result<Self> {
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(&settings.bucket)
+            .with_region(&settings.region)
+            .with_access_key_id(&settings.access_key_id)
+            .with_secret_access_key(&settings.secret_access_key)
+            .with_allow_http(settings.allow_http);
+
+        if let Some(endpoint) = &settings.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+
+        Ok(Self {
+            store: builder.build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStorage for S3Storage {
+    async fn put(&self, key: &str, mut body: ByteStream<'_>) -> Result<u64> {
+        let upload = self.store.put_multipart(&Path::from(key)).await?;
+        let mut writer = WriteMultipart::new(upload);
+        let mut size = 0;
+
+        loop {
+            let chunk = match body.try_next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    writer.abort().await?;
+                    return Err(e.into());
+                }
+            };
+
+            size += chunk.len() as u64;
+            writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+            writer.put(chunk);
+        }
+
+        writer.finish().await?;
+
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream<'static>> {
+        Ok(self
+            .store
+            .get(&Path::from(key))
+            .await?
+            .into_stream()
+            .map_err(std::io::Error::other)
+            .boxed())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        Ok(self.store.delete(&Path::from(key)).await?)
+    }
+}
@@ -12,12 +12,17 @@ use tower_sessions::Session;
 use tracing::{error, warn};
 
 use crate::{
+    api_token::{bearer_token, owner_of_token, required_scope, RouteScope},
+    groups,
     schema::{PartialUser, User},
+    settings::ProfileClaims,
     state::{AppState, SurrealDb},
     GroupClaims,
 };
 
 const USER_ID_KEY: &str = "user_id";
+/// When the ID token the user's profile was last synced from was issued
+const SYNCED_TOKEN_KEY: &str = "synced_token_issued_at";
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SessionUserId(pub RecordId);
@@ -31,57 +36,77 @@ impl SessionUserId {
             .transpose()
     }
 
+    /// Creates or updates the user the claims belong to, keeping their profile in sync with the
+    /// identity provider
     pub async fn from_claims(
         claims: &OidcClaims<GroupClaims>,
         db: &SurrealDb,
+        mapping: &ProfileClaims,
     ) -> Result<Option<Self>> {
-        Ok(
-            match db
-                .query("SELECT id FROM user WHERE subject = $subject")
-                .bind(("subject", claims.subject().clone()))
-                .await?
-                .take("id")?
-            {
-                Some(id) => Some(Self(id)),
-                None => {
-                    let user = PartialUser {
-                        subject: claims.subject().deref().clone(),
-                        email: claims.email().unwrap().deref().clone(),
-                        name: claims.name().unwrap().get(None).unwrap().to_string(),
-                    };
-
-                    db.create::<Option<User>>("user")
-                        .content(user)
-                        .await?
-                        .map(|user| Self(user.id))
-                }
+        let profile = PartialUser::from_claims(claims, mapping);
+
+        let id = match db
+            .query("SELECT id FROM user WHERE subject = $subject")
+            .bind(("subject", profile.subject.clone()))
+            .await?
+            .take("id")?
+        {
+            Some(id) => {
+                // an email address that's no longer sent is kept, as it's likely still valid
+                db.query("UPDATE $user SET name = $name, email = $email ?? email, groups = $groups, last_login = time::now()")
+                    .bind(("user", id.clone()))
+                    .bind(("name", profile.name))
+                    .bind(("email", profile.email))
+                    .bind(("groups", profile.groups.clone()))
+                    .await?
+                    .check()?;
+
+                id
+            }
+            None => match db.create::<Option<User>>("user").content(profile).await? {
+                Some(user) => user.id,
+                None => return Ok(None),
             },
-        )
+        };
+
+        groups::sync_groups(db, &id, &claims.additional_claims().groups).await?;
+
+        Ok(Some(Self(id)))
     }
 
+    /// Gets the user id from the session, syncing the user's profile from the claims first if
+    /// they haven't been synced since the user logged in
     pub async fn from_session_or_claims(
         session: Session,
         claims: &OidcClaims<GroupClaims>,
         db: &SurrealDb,
+        mapping: &ProfileClaims,
     ) -> Result<Self> {
-        match Self::from_session(session.clone()).await? {
-            Some(value) => Ok(value),
-            None => {
-                warn!("User id not found in session");
+        // every login issues a new ID token
+        let issued_at = claims.issue_time().timestamp();
 
-                Self::from_claims(claims, db)
-                    .await?
-                    .inspect(|userid| {
-                        let userid = userid.clone();
-                        tokio::task::spawn(async move { userid.to_session(session).await });
-                    })
-                    .or_else(|| {
-                        warn!("User id not found in claims");
-                        None
-                    })
-                    .ok_or_eyre("Failed to get user id")
+        if let Some(value) = Self::from_session(session.clone()).await? {
+            if session.get::<i64>(SYNCED_TOKEN_KEY).await? == Some(issued_at) {
+                return Ok(value);
             }
+        } else {
+            warn!("User id not found in session");
         }
+
+        Self::from_claims(claims, db, mapping)
+            .await?
+            .inspect(|userid| {
+                let userid = userid.clone();
+                tokio::task::spawn(async move {
+                    userid.to_session(session.clone()).await?;
+                    session.insert(SYNCED_TOKEN_KEY, issued_at).await
+                });
+            })
+            .or_else(|| {
+                warn!("User id not found in claims");
+                None
+            })
+            .ok_or_eyre("Failed to get user id")
     }
 
     pub async fn to_session(&self, session: Session) -> Result<(), tower_sessions::session::Error> {
@@ -124,6 +149,29 @@ impl FromRequestParts<AppState> for SessionUserId {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(&parts.headers) {
+            let owner = owner_of_token(&state.db, token)
+                .await
+                .map_err(|e| {
+                    error!(error = ?e, "Failed to get user id from token");
+                    (StatusCode::UNAUTHORIZED, "Failed to get user id")
+                })?
+                .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired token"))?;
+
+            if let Some(scope) =
+                required_scope(parts.extensions.get::<RouteScope>().copied(), &parts.method)
+            {
+                if !owner.has_scope(&scope) {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        "The token doesn't have the scope needed for this request",
+                    ));
+                }
+            }
+
+            return Ok(Self(owner.user));
+        }
+
         let session = Session::from_request_parts(parts, state)
             .await
             .map_err(|_| {
@@ -140,11 +188,67 @@ impl FromRequestParts<AppState> for SessionUserId {
                 (StatusCode::UNAUTHORIZED, "Failed to extract token claims")
             })?;
 
-        Self::from_session_or_claims(session, &claims, &state.db)
+        Self::from_session_or_claims(
+            session,
+            &claims,
+            &state.db,
+            &state.settings.oidc.profile_claims,
+        )
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "Failed to get user id from session or claims");
+            (StatusCode::UNAUTHORIZED, "Failed to get user id")
+        })
+    }
+}
+
+/// The id of a user who is a member of the configured admin group
+#[derive(Debug, Clone)]
+pub struct AdminUserId(pub SessionUserId);
+
+impl Deref for AdminUserId {
+    type Target = RecordId;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequestParts<AppState> for AdminUserId {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let userid = SessionUserId::from_request_parts(parts, state).await?;
+
+        let Some(admin_group) = state.settings.oidc.admin_group.clone() else {
+            return Err((StatusCode::FORBIDDEN, "No admin group is configured"));
+        };
+
+        // the groups are updated on every login, so removing someone from the group at the IdP
+        // takes effect when their session expires
+        let is_admin = state
+            .db
+            .query("SELECT VALUE (groups ?? []) CONTAINS $group FROM ONLY $user")
+            .bind(("user", userid.deref().clone()))
+            .bind(("group", admin_group))
             .await
+            .and_then(|mut response| response.take::<Option<bool>>(0))
             .map_err(|e| {
-                error!(error = ?e, "Failed to get user id from session or claims");
-                (StatusCode::UNAUTHORIZED, "Failed to get user id")
-            })
+                error!(error = ?e, "Failed to check whether the user is an admin");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to check permissions",
+                )
+            })?
+            .unwrap_or(false);
+
+        if is_admin {
+            Ok(Self(userid))
+        } else {
+            Err((StatusCode::FORBIDDEN, "Only admins can do this"))
+        }
     }
 }